@@ -1,58 +1,100 @@
 extern crate proc_macro;
-extern crate syn;
-#[macro_use]
-extern crate quote;
 
-use std::collections::HashMap;
 use proc_macro::TokenStream;
-use rs_pg_scheme::{Constraint, Field, Scheme, PgType, PkField, Action};
-
-const TABLE_NAME_ATTR: &'static str = "table_name";
-const UNIQUE_ATTR: &'static str = "unique";
-const PRIMARY_KEY_ATTR: &'static str = "primary_key";
-const REFERENCES_ATTR: &'static str = "references";
-const SKIP_ATTR: &'static str = "skip";
-const CHECK_ATTR: &'static str = "check";
-const ON_DELETE_ATTR: &'static str = "on_delete";
-const ON_UPDATE_ATTR: &'static str = "on_update";
-
-#[proc_macro_derive(Entity, attributes(table_name, primary_key, references, unique, serial, skip, check, on_delete, on_update))]
+use proc_macro2::Span;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, DeriveInput, Error, Ident, LitStr, Token};
+use rs_pg_scheme::{Constraint, Field, Scheme, PgType, PkField, Action, pg_type_name};
+
+const TABLE_NAME_ATTR: &str = "table_name";
+const UNIQUE_ATTR: &str = "unique";
+const PRIMARY_KEY_ATTR: &str = "primary_key";
+const REFERENCES_ATTR: &str = "references";
+const SKIP_ATTR: &str = "skip";
+const CHECK_ATTR: &str = "check";
+const ON_DELETE_ATTR: &str = "on_delete";
+const ON_UPDATE_ATTR: &str = "on_update";
+const DEFAULT_ATTR: &str = "default";
+
+#[proc_macro_derive(Entity, attributes(table_name, primary_key, references, unique, serial, skip, check, on_delete, on_update, default))]
 pub fn entity(input: TokenStream) -> TokenStream {
-    // Construct a string representation of the type definition
-    let s = input.to_string();
-    
-    // Parse the string representation
-    let ast = syn::parse_derive_input(&s).unwrap();
-
-    // Build the impl
-    let gen = impl_entity(&ast);
-    
-    // Return the generated impl
-    gen.parse().unwrap()
+	let ast = syn::parse_macro_input!(input as DeriveInput);
+	impl_entity(&ast)
+		.unwrap_or_else(Error::into_compile_error)
+		.into()
 }
 
-fn impl_entity(ast: &syn::DeriveInput) -> quote::Tokens {
+/// Argument of an attribute: a string literal or a bare word.
+struct Arg {
+	value: String,
+	span: Span,
+}
 
-	let type_name = &ast.ident;	
+impl Parse for Arg {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		let lookahead = input.lookahead1();
+		if lookahead.peek(LitStr) {
+			let lit: LitStr = input.parse()?;
+			Ok(Arg{value: lit.value(), span: lit.span()})
+		} else if lookahead.peek(Ident::peek_any) {
+			let ident = Ident::parse_any(input)?;
+			Ok(Arg{value: ident.to_string(), span: ident.span()})
+		} else {
+			Err(lookahead.error())
+		}
+	}
+}
 
-	let mut fields_map = HashMap::new();
+fn find_attr<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+	attrs.iter().find(|a| a.path().is_ident(name))
+}
+
+/// Arguments of `#[name(a, b)]`.
+fn attr_args(attr: &Attribute) -> syn::Result<Vec<Arg>> {
+	let list = attr.meta.require_list()?;
+	let args = list.parse_args_with(Punctuated::<Arg, Token![,]>::parse_terminated)?;
+	Ok(args.into_iter().collect())
+}
+
+/// Arguments of `#[name(..)]`, which takes exactly `n` of them.
+fn attr_args_n(attr: &Attribute, n: usize, expected: &str) -> syn::Result<Vec<Arg>> {
+	let args = attr_args(attr)?;
+	if args.len() != n {
+		return Err(Error::new_spanned(attr, format!("argument mismatch: expected {}, {} provided", expected, args.len())));
+	}
+	Ok(args)
+}
 
-	let mut has_pk = false;
-	let mut pk_name = String::new();
-	let mut pg_pk_ty = PgType::Serial;
-	let mut pk_name_ident = None;//syn::Ident{};
-	let mut pk_ty = None;
+fn impl_entity(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
 
-	let fields = get_fields(ast);	
+	let type_name = &ast.ident;
+
+	let mut scheme_fields = vec!();
+
+	// name, type, ident and rust type of every key column
+	let mut pk_columns = vec!();
+	let table_pk = get_table_pk(ast)?;
+
+	let mut column_names = vec!();
+	let mut column_idents = vec!();
+	let mut column_reads = vec!();
+	let mut skipped_idents = vec!();
+
+	let fields = get_fields(ast)?;
 	for field in fields {
-		if let Some(_) = &field.attrs.iter().find(|a| a.name() == SKIP_ATTR) {
+		let ident = field.ident.clone().unwrap();
+		if find_attr(&field.attrs, SKIP_ATTR).is_some() {
+			skipped_idents.push(ident);
 			continue;
 		}
 
 		let mut constr = vec!();
 
-		let field_name = get_field_name(&field);
-		let (field_type, str_type, is_nullable) = get_field_type(&field);
+		let field_name = ident.to_string();
+		let (field_type, is_nullable) = get_field_type(field)?;
 		constr.push(
 			if is_nullable {
 				Constraint::Null
@@ -61,74 +103,66 @@ fn impl_entity(ast: &syn::DeriveInput) -> quote::Tokens {
 			}
 		);
 
-		if let Some(attr) = &field.attrs.iter().find(|a| a.name() == CHECK_ATTR) {
-			if let syn::MetaItem::List(_, ref nested) = attr.value {
-				if nested.len() != 1 {
-					panic!("Argument mismatch. Expected check body, provided {}", nested.len());	
-				}
+		if let Some(attr) = find_attr(&field.attrs, CHECK_ATTR) {
+			let args = attr_args_n(attr, 1, "check body")?;
+			constr.push(Constraint::Check(args[0].value.clone()));
+		}
 
-				let check_body = unwrap_reference(&nested[0]);
-				constr.push(Constraint::Check(check_body));
-			}
+		if let Some(attr) = find_attr(&field.attrs, DEFAULT_ATTR) {
+			let args = attr_args_n(attr, 1, "default expression")?;
+			constr.push(Constraint::Default(args[0].value.clone()));
 		}
 
-		
-		if let Some(_) = &field.attrs.iter().find(|a| a.name() == UNIQUE_ATTR) {
+		if find_attr(&field.attrs, UNIQUE_ATTR).is_some() {
 			constr.push(Constraint::Unique);
 		}
-		
-		if let Some(_) = &field.attrs.iter().find(|a| a.name() == PRIMARY_KEY_ATTR) {
+
+		let field_pk = find_attr(&field.attrs, PRIMARY_KEY_ATTR);
+		if let Some(attr) = field_pk {
+			if !table_pk.is_empty() {
+				return Err(Error::new_spanned(attr, format!("primary key is defined both on the struct and on field {}", field_name)));
+			}
+			attr.meta.require_path_only()?;
+		}
+		if field_pk.is_some() || table_pk.iter().any(|a| a.value == field_name) {
 			if is_nullable {
-				panic!("Primary key cannot be nullable!");
+				return Err(Error::new_spanned(&field.ty, "primary key cannot be nullable"));
 			}
 			constr.push(Constraint::PrimaryKey);
-			has_pk = true;
-			pk_name = field_name.clone();
-			pg_pk_ty = field_type.clone();
-
-			println!("{:?}", field.clone().ident);
-			pk_name_ident = Some(field.clone().ident.unwrap());
-			pk_ty = Some(field.ty.clone());
+			pk_columns.push((field_name.clone(), field_type.clone(), ident.clone(), field.ty.clone()));
 		}
 
-		if let Some(attr) = &field.attrs.iter().find(|a| a.name() == REFERENCES_ATTR) {
-
-			if let syn::MetaItem::List(_, ref nested) = attr.value {
-				if nested.len() != 2 {
-					panic!("Argument mismatch. Expected 2 arguments: table and column in references attribute, {} provided", nested.len());	
-				}
+		let on_delete = find_attr(&field.attrs, ON_DELETE_ATTR);
+		let on_update = find_attr(&field.attrs, ON_UPDATE_ATTR);
+		if let Some(attr) = find_attr(&field.attrs, REFERENCES_ATTR) {
+			let args = attr_args_n(attr, 2, "table and column")?;
+			let on_delete_action = match on_delete {
+				Some(a) => Some(get_action(a)?),
+				None => None,
+			};
+			let on_update_action = match on_update {
+				Some(a) => Some(get_action(a)?),
+				None => None,
+			};
+			constr.push(Constraint::References(args[0].value.clone(), args[1].value.clone(), on_delete_action, on_update_action));
+		} else if let Some(attr) = on_delete.or(on_update) {
+			return Err(Error::new_spanned(attr, "referential action without #[references(table, column)]"));
+		}
 
-				let table_ref = unwrap_reference(&nested[0]);
-				let column_ref = unwrap_reference(&nested[1]);
-				let on_delete_action = 
-					if let Some(attr) = &field.attrs.iter().find(|a| a.name() == ON_DELETE_ATTR) {
-						if let syn::MetaItem::List(_, d) = &attr.value {
-							Some(action_from_string(&unwrap_reference(&d[0])))
-						} else {
-							panic!("On delete action should be defined")
-						}
-					} else {
-						None
-					};
-
-				let on_update_action = 
-					if let Some(attr) = &field.attrs.iter().find(|a| a.name() == ON_UPDATE_ATTR) {
-						if let syn::MetaItem::List(_, u) = &attr.value {
-							Some(action_from_string(&unwrap_reference(&u[0])))
-						} else {
-							panic!("On update action should be defined")
-						}
-					} else {
-						None
-					};
-
-				constr.push(Constraint::References(table_ref, column_ref, on_delete_action, on_update_action));
+		let expected = pg_type_name(&field_type);
+		column_reads.push(
+			if is_nullable {
+				quote! { ::rs_pg::de::get_nullable_column(row, #field_name, #expected)? }
+			} else {
+				quote! { ::rs_pg::de::get_column(row, #field_name, #expected)? }
 			}
-		}
+		);
+		column_names.push(field_name.clone());
+		column_idents.push(ident);
 
-		fields_map.insert(
-			field_name, 
+		scheme_fields.push(
 			Field{
+				name: field_name,
 				ty: field_type,
 				constraints: constr,
 			}
@@ -136,133 +170,242 @@ fn impl_entity(ast: &syn::DeriveInput) -> quote::Tokens {
 	}
 
 	let mut checks = vec!();
-	for attr in ast.attrs.iter() {
-		if (attr.name() != CHECK_ATTR) {
-			continue;
-		}
-		if let syn::MetaItem::List(_, ref nested) = attr.value {
-			if nested.len() != 1 {
-				panic!("Argument mismatch. Expected check body, provided {}", nested.len());	
-			}
+	for attr in ast.attrs.iter().filter(|a| a.path().is_ident(CHECK_ATTR)) {
+		let args = attr_args_n(attr, 1, "check body")?;
+		checks.push(Constraint::Check(args[0].value.clone()));
+	}
 
-			let check_body = unwrap_reference(&nested[0]);
-			checks.push(Constraint::Check(check_body));
+	if !table_pk.is_empty() {
+		for arg in table_pk.iter() {
+			if !pk_columns.iter().any(|c| c.0 == arg.value) {
+				return Err(Error::new(arg.span, format!("primary key column {} is not a field", arg.value)));
+			}
 		}
+		pk_columns.sort_by_key(|c| table_pk.iter().position(|a| a.value == c.0));
 	}
 
 	let scheme = Scheme{
-		name: get_table_name(ast),
-		pk_field: if has_pk { Some(PkField{ty: pg_pk_ty, name: pk_name}) } else { None },
-		fields: fields_map,
+		name: get_table_name(ast)?,
+		pk_fields: pk_columns.iter().map(|c| PkField{ty: c.1.clone(), name: c.0.clone()}).collect(),
+		fields: scheme_fields,
 		constraints: checks
 	};
 
-	let j = serde_json::to_string(&scheme).unwrap();
-	println!("{}", j);
-	let json_scheme = j.as_str();
-//	println!("{}", pk_ty);
-//	println!("{}", pk_name);
-	
-	if has_pk {
-		let ty = pk_ty.unwrap();
-		let field = pk_name_ident.unwrap();
-
-		quote! {
-			impl Entity for #type_name {
-				fn scheme() -> Scheme {
-					let scheme: Scheme = serde_json::from_str(#json_scheme).unwrap();
-					scheme
+	let scheme = scheme_tokens(&scheme);
+	let column_names = &column_names;
+	let column_idents = &column_idents;
+	let from_row = quote! {
+		impl ::rs_pg::FromRow for #type_name {
+			fn from_row(row: &::rs_pg::Row) -> Result<Self, ::rs_pg::error::DeError> {
+				Ok(#type_name{
+					#( #column_idents: #column_reads, )*
+					#( #skipped_idents: Default::default(), )*
+				})
+			}
+		}
+	};
+
+	if !pk_columns.is_empty() {
+		let pk_idents: Vec<Ident> = pk_columns.iter().map(|c| c.2.clone()).collect();
+		let pk_tys: Vec<syn::Type> = pk_columns.iter().map(|c| c.3.clone()).collect();
+		let pk_idents = &pk_idents;
+
+		let (ty, set_pk, borrow_pk) = if pk_columns.len() == 1 {
+			let field = &pk_idents[0];
+			let ty = &pk_tys[0];
+			(quote! { #ty }, quote! { self.#field = v; }, quote! { self.#field.clone() })
+		} else {
+			let vars: Vec<Ident> = (0..pk_columns.len()).map(|i| Ident::new(&format!("__pk{}", i), Span::call_site())).collect();
+			let vars = &vars;
+			(quote! { (#(#pk_tys),*) },
+			 quote! { let (#(#vars),*) = v; #( self.#pk_idents = #vars; )* },
+			 quote! { (#(self.#pk_idents.clone()),*) })
+		};
+
+		Ok(quote! {
+			impl ::rs_pg::Entity for #type_name {
+				fn scheme() -> &'static ::rs_pg::Scheme {
+					static SCHEME: ::std::sync::OnceLock<::rs_pg::Scheme> = ::std::sync::OnceLock::new();
+					SCHEME.get_or_init(|| #scheme)
+				}
+
+				fn __values(&self) -> Vec<(&'static str, &(dyn ::rs_pg::ToSql + Sync))> {
+					vec!(#( (#column_names, &self.#column_idents as &(dyn ::rs_pg::ToSql + Sync)) ),*)
 				}
 			}
 
-			impl<'a> WithId<'a, #ty> for #type_name {
-				fn __get_pk(&self) -> &#ty {
-					&self.#field
+			impl ::rs_pg::WithId<#ty> for #type_name {
+				fn __get_pk(&self) -> Vec<&(dyn ::rs_pg::ToSql + Sync)> {
+					vec!(#( &self.#pk_idents as &(dyn ::rs_pg::ToSql + Sync) ),*)
 				}
 				fn __set_pk(&mut self, v: #ty) {
-					self.#field = v;
+					#set_pk
 				}
 				fn __borrow_pk(&self) -> #ty {
-					self.#field
+					#borrow_pk
 				}
 			}
-		}
-	} else {	
-		quote! {
-			impl Entity for #type_name {
-				fn scheme() -> Scheme {
-					let scheme: Scheme = serde_json::from_str(#json_scheme).unwrap();
-					scheme
+
+			#from_row
+		})
+	} else {
+		Ok(quote! {
+			impl ::rs_pg::Entity for #type_name {
+				fn scheme() -> &'static ::rs_pg::Scheme {
+					static SCHEME: ::std::sync::OnceLock<::rs_pg::Scheme> = ::std::sync::OnceLock::new();
+					SCHEME.get_or_init(|| #scheme)
+				}
+
+				fn __values(&self) -> Vec<(&'static str, &(dyn ::rs_pg::ToSql + Sync))> {
+					vec!(#( (#column_names, &self.#column_idents as &(dyn ::rs_pg::ToSql + Sync)) ),*)
 				}
 			}
+
+			#from_row
+		})
+	}
+}
+
+/// Expression building `scheme` at runtime.
+fn scheme_tokens(scheme: &Scheme) -> proc_macro2::TokenStream {
+	let name = &scheme.name;
+	let pk_fields = scheme.pk_fields.iter().map(|f| {
+		let name = &f.name;
+		let ty = type_tokens(&f.ty);
+		quote! { ::rs_pg::PkField{name: #name.to_string(), ty: #ty} }
+	});
+	let fields = scheme.fields.iter().map(|f| {
+		let name = &f.name;
+		let ty = type_tokens(&f.ty);
+		let constraints = f.constraints.iter().map(constraint_tokens);
+		quote! { ::rs_pg::Field{name: #name.to_string(), ty: #ty, constraints: vec!(#(#constraints),*)} }
+	});
+	let constraints = scheme.constraints.iter().map(constraint_tokens);
+	quote! {
+		::rs_pg::Scheme{
+			name: #name.to_string(),
+			pk_fields: vec!(#(#pk_fields),*),
+			fields: vec!(#(#fields),*),
+			constraints: vec!(#(#constraints),*),
 		}
 	}
 }
 
-fn get_table_name(ast: &syn::DeriveInput) -> String {
-	if let Some(ref a) = &ast.attrs.iter().find(|a| a.name() == TABLE_NAME_ATTR) {
-		if let syn::MetaItem::NameValue(_, ref nested) = a.value {
-			if let syn::Lit::Str(name, _) = nested {
-				name.clone()
-			} else {
-				panic!("Table name should be defined with string");
-			}
-		} else {
-			panic!("Table name used and not defined");
+fn type_tokens(ty: &PgType) -> proc_macro2::TokenStream {
+	let variant = match ty {
+		PgType::Serial => quote!(Serial),
+		PgType::Real => quote!(Real),
+		PgType::DoublePrecision => quote!(DoublePrecision),
+		PgType::Text => quote!(Text),
+		PgType::Char => quote!(Char),
+		PgType::SmallInt => quote!(SmallInt),
+		PgType::Integer => quote!(Integer),
+		PgType::BigInt => quote!(BigInt),
+		PgType::Boolean => quote!(Boolean),
+		PgType::ByteArray => quote!(ByteArray),
+		PgType::TimeStamp => quote!(TimeStamp),
+		PgType::IpAddr => quote!(IpAddr),
+		PgType::Date => quote!(Date),
+		PgType::Time => quote!(Time),
+		PgType::Uuid => quote!(Uuid),
+	};
+	quote! { ::rs_pg::PgType::#variant }
+}
+
+fn constraint_tokens(constr: &Constraint) -> proc_macro2::TokenStream {
+	match constr {
+		Constraint::PrimaryKey => quote! { ::rs_pg::Constraint::PrimaryKey },
+		Constraint::References(table, column, on_delete, on_update) => {
+			let on_delete = action_tokens(on_delete);
+			let on_update = action_tokens(on_update);
+			quote! { ::rs_pg::Constraint::References(#table.to_string(), #column.to_string(), #on_delete, #on_update) }
 		}
-	} else {
-		format!("{}", &ast.ident)
+		Constraint::Unique => quote! { ::rs_pg::Constraint::Unique },
+		Constraint::NotNull => quote! { ::rs_pg::Constraint::NotNull },
+		Constraint::Null => quote! { ::rs_pg::Constraint::Null },
+		Constraint::Check(body) => quote! { ::rs_pg::Constraint::Check(#body.to_string()) },
+		Constraint::Default(expr) => quote! { ::rs_pg::Constraint::Default(#expr.to_string()) },
 	}
 }
 
-fn get_fields(ast: &syn::DeriveInput) -> &Vec<syn::Field> {
-	if let syn::Body::Struct(variant_data) = &ast.body {
-		if let syn::VariantData::Struct(fields) = variant_data {
-			fields
-		} else {
-			panic!("Only struct can be entity");
+fn action_tokens(action: &Option<Action>) -> proc_macro2::TokenStream {
+	let variant = match action {
+		Some(Action::Restrict) => quote!(Restrict),
+		Some(Action::Cascade) => quote!(Cascade),
+		Some(Action::SetNull) => quote!(SetNull),
+		Some(Action::SetDefault) => quote!(SetDefault),
+		Some(Action::NoAction) => quote!(NoAction),
+		None => return quote!(None),
+	};
+	quote! { Some(::rs_pg::Action::#variant) }
+}
+
+fn get_table_name(ast: &DeriveInput) -> syn::Result<String> {
+	match find_attr(&ast.attrs, TABLE_NAME_ATTR) {
+		Some(attr) => {
+			let value = &attr.meta.require_name_value()?.value;
+			match value {
+				syn::Expr::Lit(syn::ExprLit{lit: syn::Lit::Str(name), ..}) => Ok(name.value()),
+				_ => Err(Error::new_spanned(value, "table name should be a string literal")),
+			}
 		}
-	} else {
-		panic!("Only struct can be entity");
+		None => Ok(ast.ident.to_string()),
 	}
 }
 
-fn get_field_name(field: &syn::Field) -> String {
-	if let Some(ident) = &field.ident {
-		format!("{}", ident)
-	} else {
-		panic!("Fields should have names");
+/// Columns of a struct-level `#[primary_key(a, b)]`, empty if there is none.
+fn get_table_pk(ast: &DeriveInput) -> syn::Result<Vec<Arg>> {
+	match find_attr(&ast.attrs, PRIMARY_KEY_ATTR) {
+		Some(attr) => {
+			let args = match &attr.meta {
+				syn::Meta::List(_) => attr_args(attr)?,
+				_ => vec!(),
+			};
+			if args.is_empty() {
+				return Err(Error::new_spanned(attr, "primary key on a struct should list its columns"));
+			}
+			Ok(args)
+		}
+		None => Ok(vec!()),
 	}
 }
 
-fn get_field_type(field: &syn::Field) -> (PgType, String, bool) {
+fn get_fields(ast: &DeriveInput) -> syn::Result<&Punctuated<syn::Field, Token![,]>> {
+	match &ast.data {
+		syn::Data::Struct(data) => match &data.fields {
+			syn::Fields::Named(fields) => Ok(&fields.named),
+			fields => Err(Error::new_spanned(fields, "entity fields should have names")),
+		},
+		syn::Data::Enum(data) => Err(Error::new(data.enum_token.span, "only struct can be entity")),
+		syn::Data::Union(data) => Err(Error::new(data.union_token.span, "only struct can be entity")),
+	}
+}
 
-	if let syn::Ty::Path(_, path) = &field.ty {
-		let last_segment = path.segments.last().unwrap();
-		let str_type = format!("{}", last_segment.ident);
+/// Postgres type of the field and whether it is an `Option`.
+fn get_field_type(field: &syn::Field) -> syn::Result<(PgType, bool)> {
+	let last_segment = match &field.ty {
+		syn::Type::Path(path) if path.qself.is_none() => path.path.segments.last().unwrap(),
+		ty => return Err(Error::new_spanned(ty, "unsupported field type")),
+	};
 
-		if str_type == "Option" {
-			if let syn::PathParameters::AngleBracketed(angle_params) = &last_segment.parameters {
-				if let syn::Ty::Path(_, inner_path) = &angle_params.types[0] {
-					let inner_type = format!("{}", inner_path.segments.last().unwrap().ident);
-					(match_type(&inner_type), inner_type, true)
-				} else { 
-					panic!("Unsupprted Option inner type {:?}", angle_params);
+	if last_segment.ident != "Option" {
+		return Ok((match_type(&field.ty, last_segment)?, false));
+	}
+	if let syn::PathArguments::AngleBracketed(args) = &last_segment.arguments {
+		if let Some(syn::GenericArgument::Type(inner)) = args.args.first() {
+			if let syn::Type::Path(inner_path) = inner {
+				if inner_path.qself.is_none() {
+					return Ok((match_type(inner, inner_path.path.segments.last().unwrap())?, true));
 				}
-			} else {
-				panic!("Only angle parameters for Option is supported");
 			}
-		} else {
-			(match_type(&str_type), str_type, false)
+			return Err(Error::new_spanned(inner, "unsupported Option inner type"));
 		}
-	} else {
-		panic!("Unsupported field type: {:?}", field.ty);
 	}
+	Err(Error::new_spanned(&field.ty, "Option should have a type parameter"))
 }
 
-fn match_type(rust_type: &String) -> PgType {
-
-	match rust_type.as_str() {
+fn match_type(ty: &syn::Type, segment: &syn::PathSegment) -> syn::Result<PgType> {
+	Ok(match segment.ident.to_string().as_str() {
 		"f32" => PgType::Real,
 		"f64" => PgType::DoublePrecision,
 		"String" => PgType::Text,
@@ -276,37 +419,33 @@ fn match_type(rust_type: &String) -> PgType {
 		"IpAddr" => PgType::IpAddr,
 		"Date" =>  PgType::Date,
 		"Time" => PgType::Time,
-		_ => panic!("Unsupported rust type"),
-	}
+		"Uuid" => PgType::Uuid,
+		"Vec" if is_byte_vec(segment) => PgType::ByteArray,
+		_ => return Err(Error::new_spanned(ty, "unsupported rust type")),
+	})
 }
 
-fn unwrap_reference(meta_item: &syn::NestedMetaItem) -> String {
-	
-	match meta_item {
-		syn::NestedMetaItem::Literal(lit) => {
-			if let syn::Lit::Str(value, _) = lit {
-				value.to_string()
-			} else {
-				panic!("Expected table and column in references attribute")
-			}
-		}
-		syn::NestedMetaItem::MetaItem(m) => {
-			if let syn::MetaItem::Word(i) = m {
-				format!("{}", i)
-			} else {
-				panic!("Table name should be defined with string")
-			}
-		}
+/// Whether the segment is `Vec<u8>`.
+fn is_byte_vec(segment: &syn::PathSegment) -> bool {
+	match &segment.arguments {
+		syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match args.args.first() {
+			Some(syn::GenericArgument::Type(syn::Type::Path(inner))) => inner.qself.is_none() && inner.path.is_ident("u8"),
+			_ => false,
+		},
+		_ => false,
 	}
 }
 
-fn action_from_string(s: &String) -> Action {
-	match s.as_str() {
-		"Restrict" => Action::Restrict,
-		"Cascade" => Action::Cascade,
-		"SetNull" => Action::SetNull,
-		"SetDefault" => Action::SetDefault,
-		"NoAction" => Action::NoAction,
-		_ => panic!("Unexpected action: {}", s)
+/// Action of `#[on_delete(..)]` or `#[on_update(..)]`.
+fn get_action(attr: &Attribute) -> syn::Result<Action> {
+	let args = attr_args_n(attr, 1, "referential action")?;
+	let arg = &args[0];
+	match arg.value.as_str() {
+		"Restrict" => Ok(Action::Restrict),
+		"Cascade" => Ok(Action::Cascade),
+		"SetNull" => Ok(Action::SetNull),
+		"SetDefault" => Ok(Action::SetDefault),
+		"NoAction" => Ok(Action::NoAction),
+		_ => Err(Error::new(arg.span, format!("unexpected action {}, expected one of Restrict, Cascade, SetNull, SetDefault, NoAction", arg.value))),
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,20 @@
+use rs_pg::{Entity, PgType, WithId};
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+#[table_name = "blobs"]
+struct Blob {
+	#[primary_key]
+	hash: Vec<u8>,
+	data: Vec<u8>,
+	preview: Option<Vec<u8>>,
+}
+
+fn main() {
+	let scheme = Blob::scheme();
+	assert!(scheme.pk_fields[0].ty == PgType::ByteArray);
+	assert!(scheme.fields.iter().all(|f| f.ty == PgType::ByteArray));
+
+	let blob = Blob{hash: vec!(1, 2), data: vec!(), preview: None};
+	assert_eq!(blob.__borrow_pk(), vec!(1, 2));
+}
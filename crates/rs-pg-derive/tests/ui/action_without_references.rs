@@ -0,0 +1,11 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Work {
+	#[primary_key]
+	id: i32,
+	#[on_update(Cascade)]
+	person_id: i32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+#[check("salary > 100", "salary < 1000")]
+struct Work {
+	#[primary_key]
+	id: i32,
+	salary: i32,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+enum Person {
+	Named(String),
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Work {
+	#[primary_key]
+	id: i32,
+	#[default(0)]
+	salary: i32,
+}
+
+fn main() {}
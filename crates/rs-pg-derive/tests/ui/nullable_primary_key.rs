@@ -0,0 +1,10 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Person {
+	#[primary_key]
+	id: Option<i32>,
+	name: String,
+}
+
+fn main() {}
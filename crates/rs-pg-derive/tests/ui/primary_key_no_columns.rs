@@ -0,0 +1,10 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+#[primary_key]
+struct PersonWork {
+	person_id: i32,
+	work_id: i32,
+}
+
+fn main() {}
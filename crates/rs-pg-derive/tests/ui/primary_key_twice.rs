@@ -0,0 +1,11 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+#[primary_key(person_id, work_id)]
+struct PersonWork {
+	#[primary_key]
+	person_id: i32,
+	work_id: i32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Person {
+	#[primary_key(id)]
+	id: i32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+#[table_name("persons")]
+struct Person {
+	#[primary_key]
+	id: i32,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Person(i32, String);
+
+fn main() {}
@@ -0,0 +1,12 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Work {
+	#[primary_key]
+	id: i32,
+	#[references("persons", id)]
+	#[on_delete(Drop)]
+	person_id: i32,
+}
+
+fn main() {}
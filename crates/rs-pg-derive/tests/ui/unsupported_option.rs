@@ -0,0 +1,9 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Person {
+	id: i32,
+	name: Option<&'static str>,
+}
+
+fn main() {}
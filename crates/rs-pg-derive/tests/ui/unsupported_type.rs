@@ -0,0 +1,9 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Person {
+	id: i32,
+	visits: u64,
+}
+
+fn main() {}
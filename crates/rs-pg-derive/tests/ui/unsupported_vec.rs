@@ -0,0 +1,9 @@
+use rs_pg_derive::Entity;
+
+#[derive(Entity)]
+struct Person {
+	id: i32,
+	scores: Vec<i32>,
+}
+
+fn main() {}
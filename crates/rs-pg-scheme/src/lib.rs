@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum Action {
 	Restrict,
 	Cascade,
@@ -10,48 +11,59 @@ pub enum Action {
 	NoAction
 }
 
-impl ToString for Action {
-	fn to_string(&self) -> String {
+impl std::fmt::Display for Action {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
-			Action::Restrict => "RESTRICT".to_string(),
-			Action::Cascade => "CASCADE".to_string(),
-			Action::SetNull => "SET NULL".to_string(),
-			Action::SetDefault => "SET DEFAULT".to_string(),
-			Action::NoAction => "NO ACTION".to_string()
+			Action::Restrict => write!(f, "RESTRICT"),
+			Action::Cascade => write!(f, "CASCADE"),
+			Action::SetNull => write!(f, "SET NULL"),
+			Action::SetDefault => write!(f, "SET DEFAULT"),
+			Action::NoAction => write!(f, "NO ACTION")
 		}
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum Constraint {
 	PrimaryKey,
 	References(String, String, Option<Action>, Option<Action>),
 	Unique,
 	NotNull,
 	Null,
-	Check(String)
+	Check(String),
+	/// Column default, an SQL expression such as `gen_random_uuid()`.
+	Default(String)
 }
 
-impl ToString for Constraint {
-	fn to_string(&self) -> String {
+impl std::fmt::Display for Constraint {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
-			Constraint::PrimaryKey => "PRIMARY KEY".to_string(),
+			Constraint::PrimaryKey => write!(f, "PRIMARY KEY"),
 			Constraint::References(table, column, delete, update) => {
-				format!("REFERENCES {}({}) ON DELETE {} ON UPDATE {}", table, column,
-				if let Some(a) = delete { a.to_string() } else { Action::NoAction.to_string() },
-				if let Some(a) = update { a.to_string() } else { Action::NoAction.to_string() })
+				write!(f, "REFERENCES {}({}) ON DELETE {} ON UPDATE {}", quote_ident(table), quote_ident(column),
+				delete.as_ref().unwrap_or(&Action::NoAction),
+				update.as_ref().unwrap_or(&Action::NoAction))
 			}
-			Constraint::Unique => "UNIQUE".to_string(),
-			Constraint::NotNull => "NOT NULL".to_string(),
-			Constraint::Null => "NULL".to_string(),
-			Constraint::Check(body) => format!("CHECK ({})", body)
+			Constraint::Unique => write!(f, "UNIQUE"),
+			Constraint::NotNull => write!(f, "NOT NULL"),
+			Constraint::Null => write!(f, "NULL"),
+			Constraint::Check(body) => write!(f, "CHECK ({})", body),
+			Constraint::Default(expr) => write!(f, "DEFAULT {}", expr)
 		}
 	}
 }
 
+/// `name` as a quoted SQL identifier, so reserved words and mixed case
+/// names can be used as table and column names.
+pub fn quote_ident(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 pub type Serial = i32;
 
-#[derive(Deserialize, Serialize, PartialEq, Clone)]
+#[derive(PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum PgType {
 	Serial,
 	Real,
@@ -66,7 +78,8 @@ pub enum PgType {
 	TimeStamp,
 	IpAddr,
 	Date,
-	Time
+	Time,
+	Uuid
 }
 
 pub fn pg_type_to_str(ty: &PgType) -> &'static str {
@@ -84,26 +97,56 @@ pub fn pg_type_to_str(ty: &PgType) -> &'static str {
 		PgType::TimeStamp => "timestamp",
 		PgType::IpAddr => "INET",
 		PgType::Date => "DATE",
-		PgType::Time => "TIME"
+		PgType::Time => "TIME",
+		PgType::Uuid => "uuid"
+	}
+}
+
+/// Name postgres reports for the column type, as in `pg_type.typname`.
+pub fn pg_type_name(ty: &PgType) -> &'static str {
+	match ty {
+		PgType::Serial | PgType::Integer => "int4",
+		PgType::Real => "float4",
+		PgType::DoublePrecision => "float8",
+		PgType::Text => "text",
+		PgType::Char => "char",
+		PgType::SmallInt => "int2",
+		PgType::BigInt => "int8",
+		PgType::Boolean => "bool",
+		PgType::ByteArray => "bytea",
+		PgType::TimeStamp => "timestamp",
+		PgType::IpAddr => "inet",
+		PgType::Date => "date",
+		PgType::Time => "time",
+		PgType::Uuid => "uuid"
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Field {
+	pub name: String,
 	pub ty: PgType,
 	pub constraints: Vec<Constraint>
 }
 
-#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct PkField {
 	pub name: String,
 	pub ty: PgType
 }
 
-#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Scheme {
 	pub name: String,
-	pub pk_field: Option<PkField>,
-	pub fields: HashMap<String, Field>,
+	/// Primary key columns in key order, empty if there is no key.
+	pub pk_fields: Vec<PkField>,
+	/// Columns in struct declaration order.
+	pub fields: Vec<Field>,
 	pub constraints: Vec<Constraint>
-}
\ No newline at end of file
+}
+
+impl Scheme {
+	pub fn field(&self, name: &str) -> Option<&Field> {
+		self.fields.iter().find(|f| f.name == name)
+	}
+}
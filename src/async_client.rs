@@ -0,0 +1,224 @@
+//! Non-blocking counterpart of `PostgresClient` built on tokio-postgres.
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+
+use futures_util::{stream, Stream};
+use tokio::task::JoinHandle;
+use tokio_postgres::{Client, Row, ToStatement};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::ToSql;
+
+use crate::cursor::DEFAULT_FETCH_SIZE;
+use crate::database::{CreateTableOptions, ConnectOptions, UpsertOptions};
+use crate::entity::{Entity, FromRow, PrimaryKey, WithId};
+use crate::error::{DbError, ErrorInfo};
+use crate::query::Query;
+use crate::sql;
+use crate::tls::Connector;
+
+/// Mirrors the `PostgresClient` API with `async fn`s. The connection is
+/// driven by a task spawned on the current tokio runtime.
+pub struct AsyncPostgresClient {
+	client: Client,
+	connection: JoinHandle<Result<(), tokio_postgres::Error>>,
+}
+
+impl AsyncPostgresClient {
+	pub async fn connect_with_str(conn_string: &str) -> Result<AsyncPostgresClient, DbError> {
+		let conn_opts = ConnectOptions::from_url(conn_string)?;
+		let (client, connection) = Connector::for_opts(&conn_opts)?.connect_async(&conn_opts.resolved_config()).await?;
+		Ok(AsyncPostgresClient{
+			client,
+			connection,
+		})
+	}
+
+	pub async fn connect_with_opts(conn_opts: &ConnectOptions) -> Result<AsyncPostgresClient, DbError> {
+		let (client, connection) = Connector::for_opts(conn_opts)?.connect_async(&conn_opts.resolved_config()).await?;
+		Ok(AsyncPostgresClient{
+			client,
+			connection,
+		})
+	}
+
+	/// Closes the connection. Returns the error that closed it before, if
+	/// there was one.
+	pub async fn close(self) -> Result<(), DbError> {
+		drop(self.client);
+		match self.connection.await {
+			Ok(res) => Ok(res?),
+			Err(e) => Err(DbError::ConnectionLost(Box::new(ErrorInfo{message: e.to_string(), ..Default::default()}))),
+		}
+	}
+
+	pub async fn batch_execute(&self, query: &str) -> Result<(), DbError> {
+		self.client.batch_execute(query).await?;
+		Ok(())
+	}
+
+	pub async fn execute<T>(&self, query: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, DbError> where T: ?Sized + ToStatement {
+		let rows_affected = self.client.execute(query, params).await?;
+		Ok(rows_affected)
+	}
+
+	pub async fn query<T>(&self, query: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, DbError> where T: ?Sized + ToStatement {
+		let rows = self.client.query(query, params).await?;
+		Ok(rows)
+	}
+
+	pub async fn create_table<T: Entity>(&self, opts: CreateTableOptions) -> Result<(), DbError> {
+		let query = sql::create_table(sql::load_scheme::<T>()?, &opts);
+		self.client.batch_execute(query.as_str()).await?;
+		Ok(())
+	}
+
+	pub async fn insert<T: Entity>(&self, item: &T) -> Result<(), DbError> {
+		let query = sql::insert(std::slice::from_ref(item), false)?;
+		self.client.execute(query.text.as_str(), query.params().as_slice()).await?;
+		Ok(())
+	}
+
+	pub async fn insert_many<T: Entity>(&self, items: &[T]) -> Result<(), DbError> {
+		let query = sql::insert(items, false)?;
+		self.client.execute(query.text.as_str(), query.params().as_slice()).await?;
+		Ok(())
+	}
+
+	pub async fn insert_with_return<K, T>(&self, item: &mut T) -> Result<(), DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let query = sql::insert(std::slice::from_ref(item), true)?;
+		let rows = self.client.query(query.text.as_str(), query.params().as_slice()).await?;
+		let row = sql::single_row(rows)?;
+		item.__set_pk(K::from_row(&row)?);
+		Ok(())
+	}
+
+	pub async fn insert_many_with_return<K, T>(&self, items: &mut [T]) -> Result<(), DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let query = sql::insert(items, true)?;
+		let rows = self.client.query(query.text.as_str(), query.params().as_slice()).await?;
+		sql::assign_pks(items, &rows)
+	}
+
+	pub async fn copy_in<T: Entity>(&self, items: impl IntoIterator<Item = impl Borrow<T>>, batch_size: Option<usize>) -> Result<u64, DbError> {
+		let batch_size = sql::batch_size(batch_size)?;
+		let copy = sql::copy_in::<T>()?;
+		let mut items = items.into_iter().peekable();
+		let mut total = 0;
+		while items.peek().is_some() {
+			let sink = self.client.copy_in(copy.text.as_str()).await?;
+			let mut writer = std::pin::pin!(BinaryCopyInWriter::new(sink, &copy.types));
+			for item in items.by_ref().take(batch_size) {
+				writer.as_mut().write(copy.values(item.borrow())?.as_slice()).await?;
+			}
+			total += writer.finish().await?;
+		}
+		Ok(total)
+	}
+
+	pub async fn upsert<T: Entity>(&self, item: &T, opts: &UpsertOptions) -> Result<u64, DbError> {
+		let query = sql::upsert(std::slice::from_ref(item), opts, false)?;
+		Ok(self.client.execute(query.text.as_str(), query.params().as_slice()).await?)
+	}
+
+	pub async fn upsert_many<T: Entity>(&self, items: &[T], opts: &UpsertOptions) -> Result<u64, DbError> {
+		let query = sql::upsert(items, opts, false)?;
+		Ok(self.client.execute(query.text.as_str(), query.params().as_slice()).await?)
+	}
+
+	pub async fn upsert_with_return<T>(&self, item: &T, opts: &UpsertOptions) -> Result<Option<T>, DbError>
+	where T: Entity + FromRow {
+		let query = sql::upsert(std::slice::from_ref(item), opts, true)?;
+		let rows = self.client.query(query.text.as_str(), query.params().as_slice()).await?;
+		match rows.first() {
+			Some(row) => Ok(Some(T::from_row(row)?)),
+			None => Ok(None),
+		}
+	}
+
+	pub async fn select_all<T>(&self) -> Result<Vec<T>, DbError>
+	where T: Entity + FromRow {
+		let rows = self.client.query(sql::select_all::<T>()?.as_str(), &[]).await?;
+		let mut res: Vec<T> = vec!();
+		for row in rows {
+			res.push(T::from_row(&row)?);
+		}
+		Ok(res)
+	}
+
+	pub async fn fetch<T>(&self, query: &Query<T>) -> Result<Vec<T>, DbError>
+	where T: Entity + FromRow {
+		let (query, params) = query.to_sql()?;
+		let rows = self.client.query(query.as_str(), params.as_slice()).await?;
+		let mut res: Vec<T> = vec!();
+		for row in rows {
+			res.push(T::from_row(&row)?);
+		}
+		Ok(res)
+	}
+
+	/// Binds `query` to a portal and decodes the rows as they are fetched,
+	/// [`DEFAULT_FETCH_SIZE`] at a time if `fetch_size` is `None`. The portal
+	/// lives in a transaction that is rolled back when the stream is dropped.
+	pub async fn select_iter<T>(&mut self, query: &Query<T>, fetch_size: Option<u32>) -> Result<impl Stream<Item = Result<T, DbError>> + '_, DbError>
+	where T: Entity + FromRow {
+		let fetch_size = match fetch_size {
+			Some(0) => return Err(DbError::UnsupportedOperation("fetch size must be positive".to_string())),
+			Some(n) => n.min(i32::MAX as u32) as i32,
+			None => DEFAULT_FETCH_SIZE as i32,
+		};
+		let (text, params) = query.to_sql()?;
+
+		let tx = self.client.transaction().await?;
+		let portal = tx.bind(text.as_str(), params.as_slice()).await?;
+		let state = (tx, portal, VecDeque::new(), false);
+		Ok(stream::unfold(state, move |(tx, portal, mut rows, mut done)| async move {
+			if rows.is_empty() && !done {
+				match tx.query_portal(&portal, fetch_size).await {
+					Ok(fetched) => {
+						done = fetched.is_empty();
+						rows.extend(fetched);
+					}
+					Err(e) => return Some((Err(e.into()), (tx, portal, rows, true))),
+				}
+			}
+			let row: Row = rows.pop_front()?;
+			Some((T::from_row(&row).map_err(DbError::from), (tx, portal, rows, done)))
+		}))
+	}
+
+	pub async fn select_by_pk<K, T>(&self, key: K) -> Result<T, DbError>
+	where K: PrimaryKey,
+	      T: Entity + FromRow + WithId<K> {
+		let rows = self.client.query(sql::select_by_pk::<T>()?.as_str(), key.values().as_slice()).await?;
+		Ok(T::from_row(&sql::single_row(rows)?)?)
+	}
+
+	pub async fn delete_by_pk<K, T>(&self, key: K) -> Result<u64, DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let rows_affected = self.client.execute(sql::delete_by_pk::<T>()?.as_str(), key.values().as_slice()).await?;
+		Ok(rows_affected)
+	}
+
+	pub async fn count<T: Entity>(&self) -> Result<i64, DbError> {
+		let row = self.client.query_one(sql::count::<T>()?.as_str(), &[]).await?;
+		Ok(row.get(0))
+	}
+
+	pub async fn update<K, T>(&self, item: &mut T) -> Result<u64, DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let query = sql::update(item)?;
+		let rows_affected = self.client.execute(query.text.as_str(), query.params().as_slice()).await?;
+		Ok(rows_affected)
+	}
+
+	pub async fn delete_full_match<T: Entity>(&self, item: &T) -> Result<u64, DbError> {
+		let query = sql::delete_full_match(item)?;
+		let rows_affected = self.client.execute(query.text.as_str(), query.params().as_slice()).await?;
+		Ok(rows_affected)
+	}
+}
@@ -0,0 +1,558 @@
+//! Connection settings, read from code, connection strings, the
+//! environment and `~/.pgpass`.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio_postgres::config::{Config, Host};
+pub use tokio_postgres::config::TargetSessionAttrs;
+
+use crate::error::DbError;
+use crate::tls::SslMode;
+
+/// Connection settings. Everything except the TLS files ends up in a
+/// `tokio_postgres::Config`, so no value is ever spliced into a URL.
+#[derive(Clone)]
+pub struct ConnectOptions {
+	config: Config,
+	search_path: Vec<String>,
+	pub(crate) ssl_mode: SslMode,
+	pub(crate) ssl_root_cert: Option<PathBuf>,
+	pub(crate) ssl_cert: Option<PathBuf>,
+	pub(crate) ssl_key: Option<PathBuf>,
+	passfile: Option<PathBuf>,
+}
+
+impl Default for ConnectOptions {
+	fn default() -> Self {
+		ConnectOptions::new()
+	}
+}
+
+const TLS_PARAMS: [&str; 4] = ["sslmode", "sslrootcert", "sslcert", "sslkey"];
+
+impl ConnectOptions {
+	pub fn new() -> ConnectOptions {
+		ConnectOptions{
+			config: Config::new(),
+			search_path: vec!(),
+			ssl_mode: SslMode::default(),
+			ssl_root_cert: None,
+			ssl_cert: None,
+			ssl_key: None,
+			passfile: None,
+		}
+	}
+
+	/// Parses a `postgresql://` URL or a `key=value` connection string.
+	/// Besides the parameters tokio-postgres knows, `sslmode` accepts
+	/// `verify-ca`/`verify-full`, and `sslrootcert`, `sslcert`, `sslkey`
+	/// are understood.
+	pub fn from_url(conn_string: &str) -> Result<ConnectOptions, DbError> {
+		let (rest, tls_params) = split_tls_params(conn_string);
+		let config = Config::from_str(rest.as_str())?;
+		let mut opts = ConnectOptions::new();
+		opts.config = config;
+		for (key, value) in tls_params {
+			opts = opts.tls_param(key.as_str(), value.as_str())?;
+		}
+		Ok(opts)
+	}
+
+	/// Reads `DATABASE_URL` if it is set, otherwise the libpq `PG*`
+	/// variables (`PGHOST`, `PGPORT`, `PGUSER`, `PGPASSWORD`, `PGDATABASE`,
+	/// `PGAPPNAME`, `PGOPTIONS`, `PGCONNECT_TIMEOUT`,
+	/// `PGTARGETSESSIONATTRS`, `PGSSLMODE`, `PGSSLROOTCERT`, `PGSSLCERT`,
+	/// `PGSSLKEY`, `PGPASSFILE`).
+	pub fn from_env() -> Result<ConnectOptions, DbError> {
+		if let Ok(url) = env::var("DATABASE_URL") {
+			return ConnectOptions::from_url(url.as_str());
+		}
+
+		let mut opts = ConnectOptions::new();
+		if let Ok(hosts) = env::var("PGHOST") {
+			for host in hosts.split(',') {
+				opts = opts.host(host);
+			}
+		}
+		if let Ok(ports) = env::var("PGPORT") {
+			for port in ports.split(',') {
+				let port = port.trim().parse().map_err(|_| invalid("PGPORT", port))?;
+				opts = opts.port(port);
+			}
+		}
+		if let Ok(user) = env::var("PGUSER") {
+			opts = opts.user(user.as_str());
+		}
+		if let Ok(password) = env::var("PGPASSWORD") {
+			opts = opts.password(password.as_str());
+		}
+		if let Ok(dbname) = env::var("PGDATABASE") {
+			opts = opts.dbname(dbname.as_str());
+		}
+		if let Ok(name) = env::var("PGAPPNAME") {
+			opts = opts.application_name(name.as_str());
+		}
+		if let Ok(options) = env::var("PGOPTIONS") {
+			opts = opts.options(options.as_str());
+		}
+		if let Ok(timeout) = env::var("PGCONNECT_TIMEOUT") {
+			let secs = timeout.trim().parse().map_err(|_| invalid("PGCONNECT_TIMEOUT", timeout.as_str()))?;
+			opts = opts.connect_timeout(Duration::from_secs(secs));
+		}
+		if let Ok(attrs) = env::var("PGTARGETSESSIONATTRS") {
+			let attrs = match attrs.as_str() {
+				"any" => TargetSessionAttrs::Any,
+				"read-write" => TargetSessionAttrs::ReadWrite,
+				"read-only" => TargetSessionAttrs::ReadOnly,
+				_ => return Err(invalid("PGTARGETSESSIONATTRS", attrs.as_str())),
+			};
+			opts = opts.target_session_attrs(attrs);
+		}
+		for (var, key) in [("PGSSLMODE", "sslmode"), ("PGSSLROOTCERT", "sslrootcert"), ("PGSSLCERT", "sslcert"), ("PGSSLKEY", "sslkey")].iter() {
+			if let Ok(value) = env::var(var) {
+				opts = opts.tls_param(key, value.as_str())?;
+			}
+		}
+		if let Ok(path) = env::var("PGPASSFILE") {
+			opts = opts.passfile(path);
+		}
+		Ok(opts)
+	}
+
+	pub fn user(mut self, user: &str) -> Self {
+		self.config.user(user);
+		self
+	}
+
+	pub fn password(mut self, password: &str) -> Self {
+		self.config.password(password);
+		self
+	}
+
+	/// Adds a host to try. A value starting with `/` is a directory holding
+	/// the server's unix socket. Hosts are tried in order.
+	pub fn host(mut self, host: &str) -> Self {
+		self.config.host(host);
+		self
+	}
+
+	/// Adds a directory holding the server's unix socket.
+	#[cfg(unix)]
+	pub fn socket_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+		self.config.host_path(dir);
+		self
+	}
+
+	/// Port of the host added last, or of every host if given once.
+	pub fn port(mut self, port: u16) -> Self {
+		self.config.port(port);
+		self
+	}
+
+	pub fn dbname(mut self, dbname: &str) -> Self {
+		self.config.dbname(dbname);
+		self
+	}
+
+	pub fn application_name(mut self, name: &str) -> Self {
+		self.config.application_name(name);
+		self
+	}
+
+	/// Command-line options sent to the server, e.g. `-c statement_timeout=5s`.
+	pub fn options(mut self, options: &str) -> Self {
+		self.config.options(options);
+		self
+	}
+
+	/// Sets `search_path` for the session.
+	pub fn search_path(mut self, schemas: &[&str]) -> Self {
+		self.search_path = schemas.iter().map(|s| s.to_string()).collect();
+		self
+	}
+
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.config.connect_timeout(timeout);
+		self
+	}
+
+	pub fn keepalives(mut self, keepalives: bool) -> Self {
+		self.config.keepalives(keepalives);
+		self
+	}
+
+	pub fn keepalives_idle(mut self, idle: Duration) -> Self {
+		self.config.keepalives_idle(idle);
+		self
+	}
+
+	/// Which of several hosts is acceptable, e.g. only a writable primary.
+	pub fn target_session_attrs(mut self, attrs: TargetSessionAttrs) -> Self {
+		self.config.target_session_attrs(attrs);
+		self
+	}
+
+	pub fn ssl_mode(mut self, mode: SslMode) -> Self {
+		self.ssl_mode = mode;
+		self
+	}
+
+	/// PEM bundle of certificate authorities trusted for the server certificate.
+	pub fn ssl_root_cert<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.ssl_root_cert = Some(path.into());
+		self
+	}
+
+	/// PEM client certificate, sent together with `ssl_key`.
+	pub fn ssl_cert<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.ssl_cert = Some(path.into());
+		self
+	}
+
+	/// PEM (PKCS#8) private key of `ssl_cert`.
+	pub fn ssl_key<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.ssl_key = Some(path.into());
+		self
+	}
+
+	/// Password file to look the password up in when none is set. Defaults
+	/// to `~/.pgpass`. On unix, a file with group or world access is
+	/// ignored, like libpq does, but without a warning.
+	pub fn passfile<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.passfile = Some(path.into());
+		self
+	}
+
+	fn tls_param(self, key: &str, value: &str) -> Result<Self, DbError> {
+		Ok(match key {
+			"sslmode" => {
+				let mode = match value {
+					"disable" => SslMode::Disable,
+					"allow" | "prefer" => SslMode::Prefer,
+					"require" => SslMode::Require,
+					"verify-ca" => SslMode::VerifyCa,
+					"verify-full" => SslMode::VerifyFull,
+					_ => return Err(invalid(key, value)),
+				};
+				self.ssl_mode(mode)
+			}
+			"sslrootcert" => self.ssl_root_cert(value),
+			"sslcert" => self.ssl_cert(value),
+			"sslkey" => self.ssl_key(value),
+			_ => return Err(invalid(key, value)),
+		})
+	}
+
+	/// The config to connect with: libpq defaults filled in, `search_path`
+	/// merged into the options and the password looked up in the password
+	/// file if none was given.
+	pub(crate) fn resolved_config(&self) -> Config {
+		let mut config = self.config.clone();
+		if config.get_hosts().is_empty() {
+			config.host("localhost");
+		}
+		if config.get_user().is_none() {
+			if let Ok(user) = env::var("USER").or_else(|_| env::var("USERNAME")) {
+				config.user(user.as_str());
+			}
+		}
+		if !self.search_path.is_empty() {
+			let mut options = config.get_options().map(|o| format!("{} ", o)).unwrap_or_default();
+			options += format!("-c search_path={}", self.search_path.join(",").replace(' ', "\\ ")).as_str();
+			config.options(options.as_str());
+		}
+		config.ssl_mode(self.ssl_mode.to_config());
+		if config.get_password().is_none() {
+			if let Some(password) = self.lookup_passfile(&config) {
+				config.password(password);
+			}
+		}
+		config
+	}
+
+	fn lookup_passfile(&self, config: &Config) -> Option<String> {
+		let path = match &self.passfile {
+			Some(p) => p.clone(),
+			None => Path::new(&env::var_os("HOME")?).join(".pgpass"),
+		};
+		let contents = fs::read_to_string(&path).ok()?;
+
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			if let Ok(meta) = fs::metadata(&path) {
+				if meta.permissions().mode() & 0o077 != 0 {
+					return None;
+				}
+			}
+		}
+
+		let host = match config.get_hosts().first() {
+			Some(Host::Tcp(h)) => h.clone(),
+			#[cfg(unix)]
+			Some(Host::Unix(_)) => "localhost".to_string(),
+			None => "localhost".to_string(),
+		};
+		let port = config.get_ports().first().copied().unwrap_or(5432).to_string();
+		let user = config.get_user().unwrap_or("");
+		let dbname = config.get_dbname().unwrap_or(user);
+		let wanted = [host.as_str(), port.as_str(), dbname, user];
+
+		for line in contents.lines() {
+			if line.starts_with('#') || line.trim().is_empty() {
+				continue;
+			}
+			let fields = split_passfile_line(line);
+			if fields.len() != 5 {
+				continue;
+			}
+			if fields.iter().zip(wanted.iter()).all(|(f, w)| f == "*" || f == w) {
+				return Some(fields[4].clone());
+			}
+		}
+		None
+	}
+}
+
+fn invalid(key: &str, value: &str) -> DbError {
+	DbError::InvalidConfig(format!("invalid value for {}: {}", key, value))
+}
+
+/// Splits a `hostname:port:database:username:password` line, honouring
+/// `\:` and `\\` escapes.
+fn split_passfile_line(line: &str) -> Vec<String> {
+	let mut fields = vec!(String::new());
+	let mut chars = line.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => {
+				if let Some(next) = chars.next() {
+					fields.last_mut().unwrap().push(next);
+				}
+			}
+			':' if fields.len() < 5 => fields.push(String::new()),
+			_ => fields.last_mut().unwrap().push(c),
+		}
+	}
+	fields
+}
+
+/// Removes the TLS parameters tokio-postgres does not understand from a
+/// connection string, returning them separately.
+fn split_tls_params(conn_string: &str) -> (String, Vec<(String, String)>) {
+	let mut tls = vec!();
+
+	if conn_string.starts_with("postgres://") || conn_string.starts_with("postgresql://") {
+		let (base, query) = match conn_string.find('?') {
+			Some(i) => (&conn_string[..i], &conn_string[i + 1..]),
+			None => return (conn_string.to_string(), tls),
+		};
+		let mut kept = vec!();
+		for pair in query.split('&') {
+			let mut kv = pair.splitn(2, '=');
+			let key = kv.next().unwrap_or("");
+			let value = kv.next().unwrap_or("");
+			if TLS_PARAMS.contains(&key) {
+				tls.push((key.to_string(), percent_decode(value)));
+			} else {
+				kept.push(pair);
+			}
+		}
+		let rest = if kept.is_empty() {
+			base.to_string()
+		} else {
+			format!("{}?{}", base, kept.join("&"))
+		};
+		return (rest, tls);
+	}
+
+	let mut kept = vec!();
+	for (key, value, raw) in split_key_values(conn_string) {
+		if TLS_PARAMS.contains(&key.as_str()) {
+			tls.push((key, value));
+		} else {
+			kept.push(raw);
+		}
+	}
+	(kept.join(" "), tls)
+}
+
+/// Tokenizes `key=value key='quoted value'` returning the key, the
+/// unquoted value and the raw token.
+fn split_key_values(s: &str) -> Vec<(String, String, String)> {
+	let mut res = vec!();
+	let mut chars = s.chars().peekable();
+	loop {
+		while chars.peek().is_some_and(|c| c.is_whitespace()) {
+			chars.next();
+		}
+		if chars.peek().is_none() {
+			break;
+		}
+
+		let mut raw = String::new();
+		let mut key = String::new();
+		while let Some(&c) = chars.peek() {
+			if c == '=' || c.is_whitespace() {
+				break;
+			}
+			key.push(c);
+			raw.push(c);
+			chars.next();
+		}
+		while chars.peek().is_some_and(|c| c.is_whitespace()) {
+			chars.next();
+		}
+		if chars.peek() == Some(&'=') {
+			raw.push('=');
+			chars.next();
+		}
+		while chars.peek().is_some_and(|c| c.is_whitespace()) {
+			chars.next();
+		}
+
+		let mut value = String::new();
+		if chars.peek() == Some(&'\'') {
+			raw.push('\'');
+			chars.next();
+			while let Some(c) = chars.next() {
+				raw.push(c);
+				match c {
+					'\\' => {
+						if let Some(next) = chars.next() {
+							raw.push(next);
+							value.push(next);
+						}
+					}
+					'\'' => break,
+					_ => value.push(c),
+				}
+			}
+		} else {
+			while let Some(&c) = chars.peek() {
+				if c.is_whitespace() {
+					break;
+				}
+				raw.push(c);
+				value.push(c);
+				chars.next();
+			}
+		}
+		res.push((key, value, raw));
+	}
+	res
+}
+
+fn percent_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut out = vec!();
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] == b'%' && i + 2 < bytes.len() {
+			if let (Some(hi), Some(lo)) = (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2])) {
+				out.push(hi << 4 | lo);
+				i += 3;
+				continue;
+			}
+		}
+		out.push(bytes[i]);
+		i += 1;
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+	(b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn percent_decodes_bytes() {
+		assert_eq!(percent_decode("/certs/root%20ca.crt"), "/certs/root ca.crt");
+		assert_eq!(percent_decode("%D0%B9"), "й");
+		assert_eq!(percent_decode("/certs/%aé.crt"), "/certs/%aé.crt");
+		assert_eq!(percent_decode("100%"), "100%");
+		assert_eq!(percent_decode("%zz"), "%zz");
+	}
+
+	#[test]
+	fn splits_key_values() {
+		let pairs = split_key_values(" host=db  port = 5432 dbname='my db' sslmode=require ");
+		let kv: Vec<(&str, &str)> = pairs.iter().map(|(k, v, _)| (k.as_str(), v.as_str())).collect();
+		assert_eq!(kv, vec!(("host", "db"), ("port", "5432"), ("dbname", "my db"), ("sslmode", "require")));
+		assert_eq!(pairs[1].2, "port=5432");
+
+		let pairs = split_key_values("password='a \\'b\\\\c' dbname='my db'");
+		assert_eq!(pairs[0].0, "password");
+		assert_eq!(pairs[0].1, "a 'b\\c");
+		assert_eq!(pairs[0].2, "password='a \\'b\\\\c'");
+		assert_eq!(pairs[1].1, "my db");
+	}
+
+	#[test]
+	fn splits_passfile_lines() {
+		assert_eq!(split_passfile_line("db:5432:*:ann:secret"), vec!("db", "5432", "*", "ann", "secret"));
+		assert_eq!(split_passfile_line("db:5432:app:ann:pa\\:ss:word"), vec!("db", "5432", "app", "ann", "pa:ss:word"));
+		assert_eq!(split_passfile_line("db\\\\1:*:*:ann:x"), vec!("db\\1", "*", "*", "ann", "x"));
+		assert_eq!(split_passfile_line("db:5432"), vec!("db", "5432"));
+	}
+
+	#[test]
+	fn strips_tls_params() {
+		let (rest, tls) = split_tls_params("postgresql://ann@db/app?sslmode=verify-full&application_name=x&sslrootcert=/certs/r%20ca.crt");
+		assert_eq!(rest, "postgresql://ann@db/app?application_name=x");
+		assert_eq!(tls, vec!(("sslmode".to_string(), "verify-full".to_string()), ("sslrootcert".to_string(), "/certs/r ca.crt".to_string())));
+
+		let (rest, tls) = split_tls_params("postgres://db/app?sslkey=/k.pem");
+		assert_eq!(rest, "postgres://db/app");
+		assert_eq!(tls, vec!(("sslkey".to_string(), "/k.pem".to_string())));
+
+		let (rest, tls) = split_tls_params("host=db sslcert='/my certs/c.pem' user=ann");
+		assert_eq!(rest, "host=db user=ann");
+		assert_eq!(tls, vec!(("sslcert".to_string(), "/my certs/c.pem".to_string())));
+
+		let opts = ConnectOptions::from_url("host=db sslmode=verify-ca sslrootcert=/r.crt").unwrap();
+		assert_eq!(opts.ssl_mode, SslMode::VerifyCa);
+		assert_eq!(opts.ssl_root_cert, Some(PathBuf::from("/r.crt")));
+		assert!(ConnectOptions::from_url("host=db sslmode=sometimes").is_err());
+	}
+
+	#[test]
+	fn looks_up_passfile_with_wildcards() {
+		let path = env::temp_dir().join(format!("rs_pg_pgpass_{}", std::process::id()));
+		fs::write(&path, "# comment\n\
+			db:5432:app:bob:bob-secret\n\
+			db:*:app:ann:ann-secret\n\
+			*:*:*:ann:fallback\n\
+			db:5432:ap\\:p:carl:escaped\n").unwrap();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+		}
+
+		let lookup = |host: &str, port: u16, dbname: &str, user: &str| {
+			let opts = ConnectOptions::new().passfile(&path).host(host).port(port).dbname(dbname).user(user);
+			opts.lookup_passfile(&opts.config)
+		};
+		assert_eq!(lookup("db", 5432, "app", "bob"), Some("bob-secret".to_string()));
+		assert_eq!(lookup("db", 6432, "app", "ann"), Some("ann-secret".to_string()));
+		assert_eq!(lookup("other", 5432, "misc", "ann"), Some("fallback".to_string()));
+		assert_eq!(lookup("db", 5432, "ap:p", "carl"), Some("escaped".to_string()));
+		assert_eq!(lookup("db", 5432, "app", "dave"), None);
+
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+			assert_eq!(lookup("db", 5432, "app", "bob"), None);
+		}
+		fs::remove_file(&path).unwrap();
+	}
+}
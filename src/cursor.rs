@@ -0,0 +1,72 @@
+//! Row-by-row reading of large results through a server-side cursor.
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+use postgres::Row;
+
+use crate::database::PostgresClient;
+use crate::entity::{Entity, FromRow};
+use crate::error::DbError;
+use crate::query::Query;
+use crate::transaction::Transaction;
+
+/// Rows fetched per round trip when no fetch size is given.
+pub const DEFAULT_FETCH_SIZE: u32 = 1000;
+
+/// Iterator over the result of a query, fetching `fetch_size` rows at a
+/// time. The cursor lives in a transaction (a savepoint if one is already
+/// open) that ends when the iterator is dropped.
+pub struct SelectIter<'c, T> {
+	tx: Transaction<'c>,
+	fetch: String,
+	rows: VecDeque<Row>,
+	done: bool,
+	_entity: PhantomData<T>,
+}
+
+impl<'c, T> Iterator for SelectIter<'c, T>
+where T: Entity + FromRow {
+	type Item = Result<T, DbError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.rows.is_empty() && !self.done {
+			match self.tx.query(self.fetch.as_str(), &[]) {
+				Ok(rows) => {
+					self.done = rows.is_empty();
+					self.rows.extend(rows);
+				}
+				Err(e) => {
+					self.done = true;
+					return Some(Err(e));
+				}
+			}
+		}
+		let row = self.rows.pop_front()?;
+		Some(T::from_row(&row).map_err(DbError::from))
+	}
+}
+
+impl PostgresClient {
+	/// Runs `query` through `DECLARE CURSOR` and decodes the rows as they are
+	/// fetched, [`DEFAULT_FETCH_SIZE`] at a time if `fetch_size` is `None`.
+	pub fn select_iter<T>(&mut self, query: &Query<T>, fetch_size: Option<u32>) -> Result<SelectIter<'_, T>, DbError>
+	where T: Entity + FromRow {
+		let fetch_size = match fetch_size {
+			Some(0) => return Err(DbError::UnsupportedOperation("fetch size must be positive".to_string())),
+			Some(n) => n,
+			None => DEFAULT_FETCH_SIZE,
+		};
+		let (text, params) = query.to_sql()?;
+
+		let mut tx = self.begin()?;
+		let cursor = format!("rs_pg_cursor_{}", tx.transaction_depth);
+		tx.execute(format!("DECLARE {} NO SCROLL CURSOR FOR {}", cursor, text).as_str(), params.as_slice())?;
+		Ok(SelectIter{
+			tx,
+			fetch: format!("FETCH {} FROM {}", fetch_size, cursor),
+			rows: VecDeque::new(),
+			done: false,
+			_entity: PhantomData,
+		})
+	}
+}
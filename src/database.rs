@@ -1,1067 +1,238 @@
-extern crate postgres;
+use std::borrow::Borrow;
 
-use postgres::{Client, NoTls, ToStatement};
-use postgres::types::{ToSql, FromSql};
+use postgres::{Client, Row, ToStatement};
+use postgres::binary_copy::BinaryCopyInWriter;
+use postgres::types::ToSql;
 
-use serde::{
-	Deserialize, 
-	Serialize};
-
-use std::{
-	result::Result,
-	net::IpAddr,
-	time::SystemTime
-};
-use time::{
-	Time,
-	Date
-};
-use crate::entity::{Entity, WithId};
-use crate::de::from_row;
-use crate::error::{DbError, DataError};
-
-use rs_pg_scheme::{PgType, pg_type_to_str};
+use crate::entity::{Entity, FromRow, PrimaryKey, WithId};
+use crate::error::DbError;
+use crate::query::{Query, Select};
+use crate::sql;
+use crate::tls::Connector;
+pub use crate::config::ConnectOptions;
 
 pub struct CreateTableOptions {
 	pub temp: bool,
 	pub if_not_exists: bool
 }
 
-pub struct ConnectOptions {
-	pub user: String,
-	pub password: String,
-	pub address: String,
-	pub port: i32,
-	pub database: String
+/// Which uniqueness violation turns an insert into an update.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConflictTarget {
+	/// The key values of the item are sent. A serial key of 0 takes the next
+	/// value of the sequence, so new and stored rows can be mixed; keys with a
+	/// `#[default]` are refused.
+	PrimaryKey,
+	/// Columns of a unique index, e.g. a `#[unique]` field.
+	Columns(Vec<String>),
+	/// A unique or exclusion constraint by name.
+	Constraint(String),
+}
+
+/// What happens to a row that conflicts with a stored one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConflictAction {
+	DoNothing,
+	/// Overwrites every column except serial ones and the conflict target.
+	UpdateAll,
+	/// Overwrites the listed columns only.
+	Update(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+pub struct UpsertOptions {
+	pub target: ConflictTarget,
+	pub action: ConflictAction,
+}
+
+impl Default for UpsertOptions {
+	fn default() -> Self {
+		UpsertOptions{
+			target: ConflictTarget::PrimaryKey,
+			action: ConflictAction::UpdateAll,
+		}
+	}
 }
 
 pub struct PostgresClient {
 	client: Client,
+	pub(crate) transaction_depth: u32,
 }
 
 impl PostgresClient {
+	/// Connects using a `postgresql://` URL or a `key=value` connection
+	/// string, see [`ConnectOptions::from_url`].
 	pub fn connect_with_str(conn_string: &str) -> Result<PostgresClient, DbError> {
-		let client = Client::connect(conn_string, NoTls)?;
-		Ok(PostgresClient{
-			client: client,
-		})
+		PostgresClient::connect_with_opts(&ConnectOptions::from_url(conn_string)?)
 	}
 
 	pub fn connect_with_opts(conn_opts: &ConnectOptions) -> Result<PostgresClient, DbError> {
-		let client = Client::connect(format!("postgresql://{}:{}@{}:{}/{}",
-			conn_opts.user, conn_opts.password, conn_opts.address, conn_opts.port, conn_opts.database).as_str(), NoTls)?;
+		PostgresClient::connect_with(conn_opts, &Connector::for_opts(conn_opts)?)
+	}
 
+	pub(crate) fn connect_with(conn_opts: &ConnectOptions, connector: &Connector) -> Result<PostgresClient, DbError> {
+		let client = connector.connect(&conn_opts.resolved_config())?;
 		Ok(PostgresClient{
-			client: client,
+			client,
+			transaction_depth: 0,
 		})
 	}
 
+	pub fn is_closed(&self) -> bool {
+		self.client.is_closed()
+	}
+
 	pub fn batch_execute(&mut self, query: &str) -> Result<(), DbError> {
 		self.client.batch_execute(query)?;
 		Ok(())
 	}
 
-	pub fn execute<T>(&mut self, query: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, DbError> where T: ToStatement {
+	pub fn execute<T>(&mut self, query: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, DbError> where T: ?Sized + ToStatement {
 		let rows_affected = self.client.execute(query, params)?;
 		Ok(rows_affected)
 	}
 
-	pub fn create_table<T: Entity>(&mut self, opts: CreateTableOptions) -> Result<(), DbError> {
-
-		let mut query = String::from("CREATE ");
-		if opts.temp {
-			query += "TEMP ";
-		}
-		query += "TABLE ";
-		if opts.if_not_exists {
-			query += "IF NOT EXISTS ";
-		}
-		let scheme = T::scheme();
-		query += scheme.name.as_str();
-		query += "(";
-		
-		for name in scheme.fields.keys() {
-			query += name.as_str();
-			query += " ";
-
-			let field = scheme.fields.get(name).unwrap();
-			query += pg_type_to_str(&field.ty);
-			query += " ";
-
-			for constr in field.constraints.iter() {
-				query += constr.to_string().as_str();
-				query += " ";
-			}
-			query += ","
-		}
-		query.pop();
-		query += ")";
+	pub fn query<T>(&mut self, query: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, DbError> where T: ?Sized + ToStatement {
+		let rows = self.client.query(query, params)?;
+		Ok(rows)
+	}
 
-		println!("Query: \"{}\"", query);
+	pub fn create_table<T: Entity>(&mut self, opts: CreateTableOptions) -> Result<(), DbError> {
+		let query = sql::create_table(sql::load_scheme::<T>()?, &opts);
 		self.client.batch_execute(query.as_str())?;
 		Ok(())
 	}
 
-	pub fn insert<T: Entity + Serialize>(&mut self, item: &T) -> Result<(), DbError> {
-		
-		let scheme = T::scheme();
-		let val = serde_json::to_value(item).unwrap();
-	
-		println!("{}", val);
-		let mut query = String::from("INSERT INTO ");
-		query += scheme.name.as_str();
-		query += "(";
-		//TODO: error handling
-		let map = val.as_object().unwrap();
-	
-		for pair in map {
-			if scheme.fields.contains_key(pair.0) {
-				query += pair.0.as_str();
-				query += ", ";
-			}
-		}
-		query.pop();
-		query.pop();
-		query += ") VALUES (";
-	
-		let mut value_num = 1;
-
-		let mut f32s: Vec<f32> = vec!();
-		let mut f64s: Vec<f64> = vec!();
-
-		let mut i8s: Vec<i8> = vec!();
-		let mut i16s: Vec<i16> = vec!();
-		let mut i32s: Vec<i32> = vec!();
-		let mut i64s: Vec<i64> = vec!();
-
-		let mut tss: Vec<SystemTime> = vec!();
-		let mut addrs: Vec<IpAddr> = vec!();
-		let mut times: Vec<Time> = vec!();
-		let mut dates: Vec<Date> = vec!();
-
-		for pair in map {
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			if let serde_json::Value::Null = pair.1 {
-				query += "NULL, "
-			} else if field.ty == PgType::Serial {
-				query += "DEFAULT, "
-			} else {
-				query += "$";
-				query += value_num.to_string().as_str();
-				query += ", ";
-				value_num += 1;
-
-				match field.ty {
-					PgType::Real => f32s.push(unwrap_num(pair.1).as_f64().unwrap() as f32),
-					PgType::DoublePrecision => f64s.push(unwrap_num(pair.1).as_f64().unwrap()),
-					PgType::Char => i8s.push(unwrap_num(pair.1).as_i64().unwrap() as i8),
-					PgType::SmallInt => i16s.push(unwrap_num(pair.1).as_i64().unwrap() as i16),
-					PgType::Integer => i32s.push(unwrap_num(pair.1).as_i64().unwrap() as i32),
-					PgType::BigInt => i64s.push(unwrap_num(pair.1).as_i64().unwrap()),
-					PgType::IpAddr => addrs.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::TimeStamp => tss.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Time => times.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Date => dates.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					_ => {},
-				};
-			}
-		}
-		query.pop();
-		query.pop();
-		query += ")";
-
-		let mut values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!();
-
-		let mut f32_iter = f32s.iter();
-		let mut f64_iter = f64s.iter();
-
-		let mut i8_iter = i8s.iter();
-		let mut i16_iter = i16s.iter();
-		let mut i32_iter = i32s.iter();
-		let mut i64_iter = i64s.iter();
-
-		let mut ts_iter = tss.iter();
-		let mut addr_iter = addrs.iter();
-		let mut time_iter = times.iter();
-		let mut date_iter = dates.iter();
-		
-		for pair in map {
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			if let serde_json::Value::Null = pair.1 {
-				continue;
-			}
-			if field.ty == PgType::Serial {
-				continue;
-			}
-
-			match field.ty {
-				PgType::Real => values.push(f32_iter.next().unwrap()),
-				PgType::DoublePrecision => values.push(f64_iter.next().unwrap()),
-				PgType::Char => values.push(i8_iter.next().unwrap()),
-				PgType::SmallInt => values.push(i16_iter.next().unwrap()),
-				PgType::Integer => values.push(i32_iter.next().unwrap()),
-				PgType::BigInt => values.push(i64_iter.next().unwrap()),
-				PgType::TimeStamp => values.push(ts_iter.next().unwrap()),
-				PgType::IpAddr => values.push(addr_iter.next().unwrap()),
-				PgType::Time => values.push(time_iter.next().unwrap()),
-				PgType::Date => values.push(date_iter.next().unwrap()),
-				PgType::Boolean => {
-					if let serde_json::Value::Bool(b) = pair.1 {
-						values.push(b);
-					} else {
-						panic!("Expected bool, found {}", pair.1);
-					}
-				},
-				PgType::Text => {
-					if let serde_json::Value::String(s) = pair.1 {
-						values.push(s);
-					} else {
-						panic!("Expected string, found {}", pair.1);
-					}
-				},
-				_ => {},
-			};
-		}
-	
-		println!("{}", query);
-		println!("{:?}", values);
-	
-		self.client.execute(query.as_str(), values.as_slice())?;
+	pub fn insert<T: Entity>(&mut self, item: &T) -> Result<(), DbError> {
+		let query = sql::insert(std::slice::from_ref(item), false)?;
+		self.client.execute(query.text.as_str(), query.params().as_slice())?;
 		Ok(())
 	}
 
-	pub fn insert_many<T: Entity + Serialize>(&mut self, items: &Vec<T>) -> Result<(), DbError> {
-
-		if items.len() == 0 {
-			return Result::Err(DbError::DataError(DataError::EmptyVector));
-		}
-		let scheme = T::scheme();
-
-		let val = serde_json::to_value(&items[0]).unwrap();	
-		println!("{}", val);
-		let mut query = String::from("INSERT INTO ");
-		query += scheme.name.as_str();
-		query += "(";
-		//TODO: error handling
-		let map = val.as_object().unwrap();
-	
-		for pair in map {
-			if scheme.fields.contains_key(pair.0) {
-				query += pair.0.as_str();
-				query += ", ";
-			}
-		}
-
-		query.pop();
-		query.pop();
-		query += ") VALUES ";
-	
-		let mut value_num = 1;
-
-		let mut f32s: Vec<f32> = vec!();
-		let mut f64s: Vec<f64> = vec!();
-
-		let mut i8s: Vec<i8> = vec!();
-		let mut i16s: Vec<i16> = vec!();
-		let mut i32s: Vec<i32> = vec!();
-		let mut i64s: Vec<i64> = vec!();
-		
-		let mut tss: Vec<SystemTime> = vec!();
-		let mut addrs: Vec<IpAddr> = vec!();
-		let mut times: Vec<Time> = vec!();
-		let mut dates: Vec<Date> = vec!();
-		
-		for item in items {
-			query += "(";
-			let val = serde_json::to_value(item).unwrap();	
-			//TODO: error handling
-			let map = val.as_object().unwrap();
-
-			for pair in map {
-				let opt_field = scheme.fields.get(pair.0);
-				if let None = opt_field {
-					continue;
-				}
-				let field = opt_field.unwrap();
-
-				if let serde_json::Value::Null = pair.1 {
-					query += "NULL, "
-				} else if field.ty == PgType::Serial {
-					query += "DEFAULT, "
-				} else {
-					query += "$";
-					query += value_num.to_string().as_str();
-					query += ", ";
-					value_num += 1;
-			
-					match field.ty {
-						PgType::Real => f32s.push(unwrap_num(pair.1).as_f64().unwrap() as f32),
-						PgType::DoublePrecision => f64s.push(unwrap_num(pair.1).as_f64().unwrap()),
-						PgType::Char => i8s.push(unwrap_num(pair.1).as_i64().unwrap() as i8),
-						PgType::SmallInt => i16s.push(unwrap_num(pair.1).as_i64().unwrap() as i16),
-						PgType::Integer => i32s.push(unwrap_num(pair.1).as_i64().unwrap() as i32),
-						PgType::BigInt => i64s.push(unwrap_num(pair.1).as_i64().unwrap()),
-						PgType::IpAddr => addrs.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						PgType::TimeStamp => tss.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						PgType::Time => times.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						PgType::Date => dates.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						_ => {},
-					};
-				}
-			}
-			query.pop();
-			query.pop();
-			query += "),";
-		}
-		query.pop();
-
-		let mut j_values = vec!();
-		let mut j_maps = vec!();
-		for item in items {
-			let val = serde_json::to_value(item).unwrap();
-			j_values.push(val);
-		}
-		for i in 0..j_values.len() {
-			let map = j_values[i].as_object().unwrap();
-			j_maps.push(map);
-		}
-
-		let mut values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!();
-
-		let mut f32_iter = f32s.iter();
-		let mut f64_iter = f64s.iter();
-
-		let mut i8_iter = i8s.iter();
-		let mut i16_iter = i16s.iter();
-		let mut i32_iter = i32s.iter();
-		let mut i64_iter = i64s.iter();
-
-		let mut ts_iter = tss.iter();
-		let mut addr_iter = addrs.iter();
-		let mut time_iter = times.iter();
-		let mut date_iter = dates.iter();
-
-		for map in j_maps {
-			for pair in map {
-				let opt_field = scheme.fields.get(pair.0);
-				if let None = opt_field {
-					continue;
-				}
-				let field = opt_field.unwrap();
-
-				if let serde_json::Value::Null = pair.1 {
-					continue;
-				}
-				if field.ty == PgType::Serial {
-					continue;
-				}
-
-				match field.ty {
-					PgType::Real => values.push(f32_iter.next().unwrap()),
-					PgType::DoublePrecision => values.push(f64_iter.next().unwrap()),
-					PgType::Char => values.push(i8_iter.next().unwrap()),
-					PgType::SmallInt => values.push(i16_iter.next().unwrap()),
-					PgType::Integer => values.push(i32_iter.next().unwrap()),
-					PgType::BigInt => values.push(i64_iter.next().unwrap()),
-					PgType::TimeStamp => values.push(ts_iter.next().unwrap()),
-					PgType::IpAddr => values.push(addr_iter.next().unwrap()),
-					PgType::Time => values.push(time_iter.next().unwrap()),
-					PgType::Date => values.push(date_iter.next().unwrap()),
-					PgType::Boolean => {
-						if let serde_json::Value::Bool(b) = pair.1 {
-							values.push(b);
-						} else {
-							panic!("Expected bool, found {}", pair.1);
-						}
-					},
-					PgType::Text => {
-						if let serde_json::Value::String(s) = pair.1 {
-							values.push(s);
-						} else {
-							panic!("Expected string, found {}", pair.1);
-						}
-					},
-					_ => {},
-				};
-			}
-		}
-	
-		println!("{}", query);
-		println!("{:?}", values);
-	
-		self.client.execute(query.as_str(), values.as_slice())?;
+	pub fn insert_many<T: Entity>(&mut self, items: &[T]) -> Result<(), DbError> {
+		let query = sql::insert(items, false)?;
+		self.client.execute(query.text.as_str(), query.params().as_slice())?;
 		Ok(())
 	}
 
-	pub fn insert_with_return<'b, P, T>(&mut self, item: &mut T) -> Result<(), DbError>
-	where P: for<'a> FromSql<'a> + ToSql,
-	      T: Entity + WithId<'b, P> + Serialize {
-
-		let scheme = T::scheme();
-		let val = serde_json::to_value(&item).unwrap();
-	
-		println!("{}", val);
-		let mut query = String::from("INSERT INTO ");
-		query += scheme.name.as_str();
-		query += "(";
-		//TODO: error handling
-		let map = val.as_object().unwrap();
-	
-		for pair in map {
-			if scheme.fields.contains_key(pair.0) {
-				query += pair.0.as_str();
-				query += ", ";
-			}
-		}
-		query.pop();
-		query.pop();
-		query += ") VALUES (";
-	
-		let mut value_num = 1;
-
-		let mut f32s: Vec<f32> = vec!();
-		let mut f64s: Vec<f64> = vec!();
-
-		let mut i8s: Vec<i8> = vec!();
-		let mut i16s: Vec<i16> = vec!();
-		let mut i32s: Vec<i32> = vec!();
-		let mut i64s: Vec<i64> = vec!();
-
-		let mut tss: Vec<SystemTime> = vec!();
-		let mut addrs: Vec<IpAddr> = vec!();
-		let mut times: Vec<Time> = vec!();
-		let mut dates: Vec<Date> = vec!();
-		
-		for pair in map {
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			if let serde_json::Value::Null = pair.1 {
-				query += "NULL, "
-			} else if field.ty == PgType::Serial {
-				query += "DEFAULT, "
-			} else {
-				query += "$";
-				query += value_num.to_string().as_str();
-				query += ", ";
-				value_num += 1;
-		
-				match field.ty {
-					PgType::Real => f32s.push(unwrap_num(pair.1).as_f64().unwrap() as f32),
-					PgType::DoublePrecision => f64s.push(unwrap_num(pair.1).as_f64().unwrap()),
-					PgType::Char => i8s.push(unwrap_num(pair.1).as_i64().unwrap() as i8),
-					PgType::SmallInt => i16s.push(unwrap_num(pair.1).as_i64().unwrap() as i16),
-					PgType::Integer => i32s.push(unwrap_num(pair.1).as_i64().unwrap() as i32),
-					PgType::BigInt => i64s.push(unwrap_num(pair.1).as_i64().unwrap()),
-					PgType::IpAddr => addrs.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::TimeStamp => tss.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Time => times.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Date => dates.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					_ => {},
-				};
-			}
-		}
-		query.pop();
-		query.pop();
-		query += ") RETURNING ";
-		query += scheme.pk_field.unwrap().name.as_str();
-
-		let mut values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!();
-
-		let mut f32_iter = f32s.iter();
-		let mut f64_iter = f64s.iter();
-
-		let mut i8_iter = i8s.iter();
-		let mut i16_iter = i16s.iter();
-		let mut i32_iter = i32s.iter();
-		let mut i64_iter = i64s.iter();
-
-		let mut ts_iter = tss.iter();
-		let mut addr_iter = addrs.iter();
-		let mut time_iter = times.iter();
-		let mut date_iter = dates.iter();
-	
-		for pair in map {
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			if let serde_json::Value::Null = pair.1 {
-				continue;
-			}
-			if field.ty == PgType::Serial {
-				continue;
-			}
-
-			match field.ty {
-				PgType::Real => values.push(f32_iter.next().unwrap()),
-				PgType::DoublePrecision => values.push(f64_iter.next().unwrap()),
-				PgType::Char => values.push(i8_iter.next().unwrap()),
-				PgType::SmallInt => values.push(i16_iter.next().unwrap()),
-				PgType::Integer => values.push(i32_iter.next().unwrap()),
-				PgType::BigInt => values.push(i64_iter.next().unwrap()),
-				PgType::TimeStamp => values.push(ts_iter.next().unwrap()),
-				PgType::IpAddr => values.push(addr_iter.next().unwrap()),
-				PgType::Time => values.push(time_iter.next().unwrap()),
-				PgType::Date => values.push(date_iter.next().unwrap()),
-				PgType::Boolean => {
-					if let serde_json::Value::Bool(b) = pair.1 {
-						values.push(b);
-					} else {
-						panic!("Expected bool, found {}", pair.1);
-					}
-				},
-				PgType::Text => {
-					if let serde_json::Value::String(s) = pair.1 {
-						values.push(s);
-					} else {
-						panic!("Expected string, found {}", pair.1);
-					}
-				},
-				_ => {},
-			};
-		}
-	
-		println!("{}", query);
-		println!("{:?}", values);
-	
-		let rows = self.client.query(query.as_str(), values.as_slice())?;
-
-		if rows.len() == 0 {
-			Result::Err(DbError::DataError(DataError::ZeroRecordReturned))
-		} else if rows.len() > 1 {
-			Result::Err(DbError::DataError(DataError::MoreThan1RecordReturned))
-		} else {
-			let id: P = rows[0].get(0);
-			item.__set_pk(id);
-			Ok(())
-		}
+	pub fn insert_with_return<K, T>(&mut self, item: &mut T) -> Result<(), DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let query = sql::insert(std::slice::from_ref(item), true)?;
+		let rows = self.client.query(query.text.as_str(), query.params().as_slice())?;
+		let row = sql::single_row(rows)?;
+		item.__set_pk(K::from_row(&row)?);
+		Ok(())
 	}
 
-	pub fn insert_many_with_return<'b, P, T>(&mut self, items: &mut Vec<T>) -> Result<(), DbError> 
-	where P: for<'a> FromSql<'a> + ToSql,
-		  T: Entity + WithId<'b, P> + Serialize {
-
-		if items.len() == 0 {
-			return Result::Err(DbError::DataError(DataError::EmptyVector));
-		}
-		let scheme = T::scheme();
-
-		let val = serde_json::to_value(&items[0]).unwrap();	
-		println!("{}", val);
-		let mut query = String::from("INSERT INTO ");
-		query += scheme.name.as_str();
-		query += "(";
-		//TODO: error handling
-		let map = val.as_object().unwrap();
-	
-		for pair in map {
-			if scheme.fields.contains_key(pair.0) {
-				query += pair.0.as_str();
-				query += ", ";
-			}
-		}
-
-		query.pop();
-		query.pop();
-		query += ") VALUES ";
-	
-		let mut value_num = 1;
-
-		let mut f32s: Vec<f32> = vec!();
-		let mut f64s: Vec<f64> = vec!();
-
-		let mut i8s: Vec<i8> = vec!();
-		let mut i16s: Vec<i16> = vec!();
-		let mut i32s: Vec<i32> = vec!();
-		let mut i64s: Vec<i64> = vec!();
-		
-		let mut tss: Vec<SystemTime> = vec!();
-		let mut addrs: Vec<IpAddr> = vec!();
-		let mut times: Vec<Time> = vec!();
-		let mut dates: Vec<Date> = vec!();
-
-		for i in 0..items.len() {
-			query += "(";
-			let val = serde_json::to_value(&items[i]).unwrap();	
-			//TODO: error handling
-			let map = val.as_object().unwrap();
-
-			for pair in map {
-				let opt_field = scheme.fields.get(pair.0);
-				if let None = opt_field {
-					continue;
-				}
-				let field = opt_field.unwrap();
+	pub fn insert_many_with_return<K, T>(&mut self, items: &mut [T]) -> Result<(), DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let query = sql::insert(items, true)?;
+		let rows = self.client.query(query.text.as_str(), query.params().as_slice())?;
+		sql::assign_pks(items, &rows)
+	}
 
-				if let serde_json::Value::Null = pair.1 {
-					query += "NULL, "
-				} else if field.ty == PgType::Serial {
-					query += "DEFAULT, "
-				} else {
-					query += "$";
-					query += value_num.to_string().as_str();
-					query += ", ";
-					value_num += 1;
-			
-					match field.ty {
-						PgType::Real => f32s.push(unwrap_num(pair.1).as_f64().unwrap() as f32),
-						PgType::DoublePrecision => f64s.push(unwrap_num(pair.1).as_f64().unwrap()),
-						PgType::Char => i8s.push(unwrap_num(pair.1).as_i64().unwrap() as i8),
-						PgType::SmallInt => i16s.push(unwrap_num(pair.1).as_i64().unwrap() as i16),
-						PgType::Integer => i32s.push(unwrap_num(pair.1).as_i64().unwrap() as i32),
-						PgType::BigInt => i64s.push(unwrap_num(pair.1).as_i64().unwrap()),
-						PgType::IpAddr => addrs.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						PgType::TimeStamp => tss.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						PgType::Time => times.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						PgType::Date => dates.push(serde_json::from_value(pair.1.clone()).unwrap()),
-						_ => {},
-					};
-				}
+	/// Loads `items` with binary `COPY`, `batch_size` rows per statement
+	/// (all in one if `None`). Returns the number of loaded rows. Batches
+	/// loaded before a failing one stay unless run in a transaction.
+	pub fn copy_in<T: Entity>(&mut self, items: impl IntoIterator<Item = impl Borrow<T>>, batch_size: Option<usize>) -> Result<u64, DbError> {
+		let batch_size = sql::batch_size(batch_size)?;
+		let copy = sql::copy_in::<T>()?;
+		let mut items = items.into_iter().peekable();
+		let mut total = 0;
+		while items.peek().is_some() {
+			let mut writer = BinaryCopyInWriter::new(self.client.copy_in(copy.text.as_str())?, &copy.types);
+			for item in items.by_ref().take(batch_size) {
+				writer.write(copy.values(item.borrow())?.as_slice())?;
 			}
-			query.pop();
-			query.pop();
-			query += "),";
-		}
-		query.pop();
-		query += format!(" RETURNING {}", scheme.pk_field.unwrap().name).as_str();
-
-		let mut j_values = vec!();
-		let mut j_maps = vec!();
-		for i in 0..items.len() {
-			let val = serde_json::to_value(&items[i]).unwrap();
-			j_values.push(val);
+			total += writer.finish()?;
 		}
-		for i in 0..j_values.len() {
-			let map = j_values[i].as_object().unwrap();
-			j_maps.push(map);
-		}
-
-		let mut values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!();
-
-		let mut f32_iter = f32s.iter();
-		let mut f64_iter = f64s.iter();
-
-		let mut i8_iter = i8s.iter();
-		let mut i16_iter = i16s.iter();
-		let mut i32_iter = i32s.iter();
-		let mut i64_iter = i64s.iter();
-
-		let mut ts_iter = tss.iter();
-		let mut addr_iter = addrs.iter();
-		let mut time_iter = times.iter();
-		let mut date_iter = dates.iter();
-
-		for map in j_maps {
-			for pair in map {
-				let opt_field = scheme.fields.get(pair.0);
-				if let None = opt_field {
-					continue;
-				}
-				let field = opt_field.unwrap();
-
-				if let serde_json::Value::Null = pair.1 {
-					continue;
-				}
-				if field.ty == PgType::Serial {
-					continue;
-				}
+		Ok(total)
+	}
 
-				match field.ty {
-					PgType::Real => values.push(f32_iter.next().unwrap()),
-					PgType::DoublePrecision => values.push(f64_iter.next().unwrap()),
-					PgType::Char => values.push(i8_iter.next().unwrap()),
-					PgType::SmallInt => values.push(i16_iter.next().unwrap()),
-					PgType::Integer => values.push(i32_iter.next().unwrap()),
-					PgType::BigInt => values.push(i64_iter.next().unwrap()),
-					PgType::TimeStamp => values.push(ts_iter.next().unwrap()),
-					PgType::IpAddr => values.push(addr_iter.next().unwrap()),
-					PgType::Time => values.push(time_iter.next().unwrap()),
-					PgType::Date => values.push(date_iter.next().unwrap()),
-					PgType::Boolean => {
-						if let serde_json::Value::Bool(b) = pair.1 {
-							values.push(b);
-						} else {
-							panic!("Expected bool, found {}", pair.1);
-						}
-					},
-					PgType::Text => {
-						if let serde_json::Value::String(s) = pair.1 {
-							values.push(s);
-						} else {
-							panic!("Expected string, found {}", pair.1);
-						}
-					},
-					_ => {},
-				};
-			}
-		}
-	
-		println!("{}", query);
-		println!("{:?}", values);
+	/// Inserts `item` or resolves the conflict with a stored row as `opts`
+	/// says. Returns the number of inserted or updated rows.
+	pub fn upsert<T: Entity>(&mut self, item: &T, opts: &UpsertOptions) -> Result<u64, DbError> {
+		let query = sql::upsert(std::slice::from_ref(item), opts, false)?;
+		Ok(self.client.execute(query.text.as_str(), query.params().as_slice())?)
+	}
 
-		let rows = self.client.query(query.as_str(), values.as_slice())?;
+	/// Same as `upsert` for many rows in one statement. The rows may not
+	/// conflict with each other when updating.
+	pub fn upsert_many<T: Entity>(&mut self, items: &[T], opts: &UpsertOptions) -> Result<u64, DbError> {
+		let query = sql::upsert(items, opts, false)?;
+		Ok(self.client.execute(query.text.as_str(), query.params().as_slice())?)
+	}
 
-		if rows.len() == 0 {
-			Result::Err(DbError::DataError(DataError::ZeroRecordReturned))
-		} else if rows.len() != items.len() {
-			Result::Err(DbError::DataError(DataError::WrongNumberOfRecordsReturned(items.len(), rows.len())))
-		} else {
-			let mut i = 0;
-			for item in items.iter_mut() {
-				let id: P = rows[i].get(0);
-				item.__set_pk(id);
-				i += 1;
-			}
-			Ok(())
+	/// Upserts `item` and returns the row as stored, `None` if it was
+	/// skipped by `DoNothing`.
+	pub fn upsert_with_return<T>(&mut self, item: &T, opts: &UpsertOptions) -> Result<Option<T>, DbError>
+	where T: Entity + FromRow {
+		let query = sql::upsert(std::slice::from_ref(item), opts, true)?;
+		let rows = self.client.query(query.text.as_str(), query.params().as_slice())?;
+		match rows.first() {
+			Some(row) => Ok(Some(T::from_row(row)?)),
+			None => Ok(None),
 		}
 	}
 
 	pub fn select_all<T>(&mut self) -> Result<Vec<T>, DbError>
-	where T: for<'de> serde::Deserialize<'de> + Entity + Serialize {
-
-		let scheme = T::scheme();
-		let query = format!("SELECT * FROM {}", scheme.name);
-		let rows = self.client.query(query.as_str(), &[])?;
+	where T: Entity + FromRow {
+		let rows = self.client.query(sql::select_all::<T>()?.as_str(), &[])?;
 		let mut res: Vec<T> = vec!();
 		for row in rows {
-			res.push(from_row(row)?);
+			res.push(T::from_row(&row)?);
 		}
 		Ok(res)
 	}
 
-	pub fn select_by_pk<'a, P, T>(&mut self, v: P) -> Result<T, DbError>
-	where P: ToSql + FromSql<'a> + std::marker::Sync,
-	      T: for<'de> serde::Deserialize<'de> + Entity + WithId<'a, P> + Serialize {
+	pub fn select<T>(&mut self) -> Select<'_, T>
+	where T: Entity + FromRow {
+		Select::new(self)
+	}
 
-		let values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!(&v);
-		let scheme = T::scheme();
-		let query = format!(r#"SELECT * FROM {}
-							WHERE {} = $1"#, scheme.name, scheme.pk_field.unwrap().name);
-		let mut rows = self.client.query(query.as_str(), values.as_slice())?;
-		if rows.len() == 0 {
-			Result::Err(DbError::DataError(DataError::ZeroRecordReturned))
-		} else if rows.len() > 1 {
-			Result::Err(DbError::DataError(DataError::MoreThan1RecordReturned))
-		} else {
-			Ok(from_row(rows.remove(0))?)
+	pub fn fetch<T>(&mut self, query: &Query<T>) -> Result<Vec<T>, DbError>
+	where T: Entity + FromRow {
+		let (query, params) = query.to_sql()?;
+		let rows = self.client.query(query.as_str(), params.as_slice())?;
+		let mut res: Vec<T> = vec!();
+		for row in rows {
+			res.push(T::from_row(&row)?);
 		}
+		Ok(res)
 	}
 
-	pub fn delete_by_pk<'a, P, T>(&mut self, v: P) -> Result<u64, DbError>
-	where P: ToSql + FromSql<'a> + std::marker::Sync,
-	      T: Entity + WithId<'a, P> {
+	pub fn select_by_pk<K, T>(&mut self, key: K) -> Result<T, DbError>
+	where K: PrimaryKey,
+	      T: Entity + FromRow + WithId<K> {
+		let rows = self.client.query(sql::select_by_pk::<T>()?.as_str(), key.values().as_slice())?;
+		Ok(T::from_row(&sql::single_row(rows)?)?)
+	}
 
-		let values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!(&v);
-		let scheme = T::scheme();
-		let query = format!(r#"DELETE FROM {}
-							WHERE {} = $1"#, scheme.name, scheme.pk_field.unwrap().name);
-		let rows_affected = self.client.execute(query.as_str(), values.as_slice())?;
+	pub fn delete_by_pk<K, T>(&mut self, key: K) -> Result<u64, DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let rows_affected = self.client.execute(sql::delete_by_pk::<T>()?.as_str(), key.values().as_slice())?;
 		Ok(rows_affected)
 	}
 
 	pub fn count<T: Entity>(&mut self) -> Result<i64, DbError> {
-		let scheme = T::scheme();
-		let query = format!("SELECT COUNT(*) FROM {}", scheme.name);
-		let rows = self.client.query(query.as_str(), &[])?;
-		Ok(rows[0].get(0))
+		let row = self.client.query_one(sql::count::<T>()?.as_str(), &[])?;
+		Ok(row.get(0))
 	}
 
-	pub fn update<'a, P, T>(&mut self, item: &mut T) -> Result<u64, DbError>
-	where P: ToSql + FromSql<'a> + std::marker::Sync,
-	      T: Entity + WithId<'a, P> + Serialize {
-
-		let scheme = T::scheme();
-		let val = serde_json::to_value(&item).unwrap();
-		let pk_name = scheme.pk_field.unwrap().name;
-	
-		println!("{}", val);
-		let mut query = String::new();
-
-		//TODO: error handling
-		let map = val.as_object().unwrap();
-	
-		let mut value_num = 1;
-
-		let mut f32s: Vec<f32> = vec!();
-		let mut f64s: Vec<f64> = vec!();
-
-		let mut i8s: Vec<i8> = vec!();
-		let mut i16s: Vec<i16> = vec!();
-		let mut i32s: Vec<i32> = vec!();
-		let mut i64s: Vec<i64> = vec!();
-
-		let mut tss: Vec<SystemTime> = vec!();
-		let mut addrs: Vec<IpAddr> = vec!();
-		let mut times: Vec<Time> = vec!();
-		let mut dates: Vec<Date> = vec!();
-		
-		for pair in map {
-			if pair.0.as_str() == pk_name {
-				continue;
-			}
-
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			query += pair.0.as_str();
-			query += " = ";
-
-			if let serde_json::Value::Null = pair.1 {
-				query += "NULL, "
-			} else if field.ty == PgType::Serial {
-				query += "DEFAULT, "
-			} else {
-				query += "$";
-				query += value_num.to_string().as_str();
-				query += ", ";
-				value_num += 1;
-		
-				match field.ty {
-					PgType::Real => f32s.push(unwrap_num(pair.1).as_f64().unwrap() as f32),
-					PgType::DoublePrecision => f64s.push(unwrap_num(pair.1).as_f64().unwrap()),
-					PgType::Char => i8s.push(unwrap_num(pair.1).as_i64().unwrap() as i8),
-					PgType::SmallInt => i16s.push(unwrap_num(pair.1).as_i64().unwrap() as i16),
-					PgType::Integer => i32s.push(unwrap_num(pair.1).as_i64().unwrap() as i32),
-					PgType::BigInt => i64s.push(unwrap_num(pair.1).as_i64().unwrap()),
-					PgType::IpAddr => addrs.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::TimeStamp => tss.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Time => times.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Date => dates.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					_ => {},
-				};
-			}
-		}
-		query.pop();
-		query.pop();
-
-		let mut values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!();
-
-		let mut f32_iter = f32s.iter();
-		let mut f64_iter = f64s.iter();
-
-		let mut i8_iter = i8s.iter();
-		let mut i16_iter = i16s.iter();
-		let mut i32_iter = i32s.iter();
-		let mut i64_iter = i64s.iter();
-
-		let mut ts_iter = tss.iter();
-		let mut addr_iter = addrs.iter();
-		let mut time_iter = times.iter();
-		let mut date_iter = dates.iter();
-	
-		for pair in map {
-			if pair.0.as_str() == pk_name {
-				continue;
-			}
-		
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			if let serde_json::Value::Null = pair.1 {
-				continue;
-			}
-			if field.ty == PgType::Serial {
-				continue;
-			}
-
-			match field.ty {
-				PgType::Real => values.push(f32_iter.next().unwrap()),
-				PgType::DoublePrecision => values.push(f64_iter.next().unwrap()),
-				PgType::Char => values.push(i8_iter.next().unwrap()),
-				PgType::SmallInt => values.push(i16_iter.next().unwrap()),
-				PgType::Integer => values.push(i32_iter.next().unwrap()),
-				PgType::BigInt => values.push(i64_iter.next().unwrap()),
-				PgType::TimeStamp => values.push(ts_iter.next().unwrap()),
-				PgType::IpAddr => values.push(addr_iter.next().unwrap()),
-				PgType::Time => values.push(time_iter.next().unwrap()),
-				PgType::Date => values.push(date_iter.next().unwrap()),
-				PgType::Boolean => {
-					if let serde_json::Value::Bool(b) = pair.1 {
-						values.push(b);
-					} else {
-						panic!("Expected bool, found {}", pair.1);
-					}
-				},
-				PgType::Text => {
-					if let serde_json::Value::String(s) = pair.1 {
-						values.push(s);
-					} else {
-						panic!("Expected string, found {}", pair.1);
-					}
-				},
-				_ => {},
-			};
-		}
-	
-		println!("{}", query);
-		println!("{:?}", values);
-
-		query = format!("UPDATE {} SET {} WHERE {} = ${}", scheme.name, query, pk_name, value_num.to_string());
-		values.push(item.__get_pk());
-	
-		let rows_affected = self.client.execute(query.as_str(), values.as_slice())?;
+	pub fn update<K, T>(&mut self, item: &mut T) -> Result<u64, DbError>
+	where K: PrimaryKey,
+	      T: Entity + WithId<K> {
+		let query = sql::update(item)?;
+		let rows_affected = self.client.execute(query.text.as_str(), query.params().as_slice())?;
 		Ok(rows_affected)
 	}
 
-	pub fn delete_full_match<T: Entity + Serialize>(&mut self, item: &T) -> Result<u64, DbError> {
-		
-		let scheme = T::scheme();
-		let val = serde_json::to_value(item).unwrap();
-	
-		println!("{}", val);
-		let mut query = format!("DELETE FROM {} WHERE ", scheme.name);
-
-		//TODO: error handling
-		let map = val.as_object().unwrap();
-		
-		let mut value_num = 1;
-
-		let mut f32s: Vec<f32> = vec!();
-		let mut f64s: Vec<f64> = vec!();
-
-		let mut i8s: Vec<i8> = vec!();
-		let mut i16s: Vec<i16> = vec!();
-		let mut i32s: Vec<i32> = vec!();
-		let mut i64s: Vec<i64> = vec!();
-
-		let mut tss: Vec<SystemTime> = vec!();
-		let mut addrs: Vec<IpAddr> = vec!();
-		let mut times: Vec<Time> = vec!();
-		let mut dates: Vec<Date> = vec!();
-		
-		for pair in map {
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-			if field.ty == PgType::Serial {
-				continue;
-			}
-
-			query += pair.0.as_str();
-
-			if let serde_json::Value::Null = pair.1 {
-				query += "IS NULL"
-			} else {
-				query += " = $";
-				query += value_num.to_string().as_str();
-				value_num += 1;
-		
-				match field.ty {
-					PgType::Real => f32s.push(unwrap_num(pair.1).as_f64().unwrap() as f32),
-					PgType::DoublePrecision => f64s.push(unwrap_num(pair.1).as_f64().unwrap()),
-					PgType::Char => i8s.push(unwrap_num(pair.1).as_i64().unwrap() as i8),
-					PgType::SmallInt => i16s.push(unwrap_num(pair.1).as_i64().unwrap() as i16),
-					PgType::Integer => i32s.push(unwrap_num(pair.1).as_i64().unwrap() as i32),
-					PgType::BigInt => i64s.push(unwrap_num(pair.1).as_i64().unwrap()),
-					PgType::IpAddr => addrs.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::TimeStamp => tss.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Time => times.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					PgType::Date => dates.push(serde_json::from_value(pair.1.clone()).unwrap()),
-					_ => {},
-				};
-			}
-			query += " AND ";
-		}
-		query.pop();
-		query.pop();
-		query.pop();
-		query.pop();
-		query.pop();
-
-		let mut values: Vec<&(dyn postgres::types::ToSql + Sync)> = vec!();
-
-		let mut f32_iter = f32s.iter();
-		let mut f64_iter = f64s.iter();
-
-		let mut i8_iter = i8s.iter();
-		let mut i16_iter = i16s.iter();
-		let mut i32_iter = i32s.iter();
-		let mut i64_iter = i64s.iter();
-
-		let mut ts_iter = tss.iter();
-		let mut addr_iter = addrs.iter();
-		let mut time_iter = times.iter();
-		let mut date_iter = dates.iter();
-	
-		for pair in map {
-			let opt_field = scheme.fields.get(pair.0);
-			if let None = opt_field {
-				continue;
-			}
-			let field = opt_field.unwrap();
-
-			if let serde_json::Value::Null = pair.1 {
-				continue;
-			}
-			if field.ty == PgType::Serial {
-				continue;
-			}
-
-			match field.ty {
-				PgType::Real => values.push(f32_iter.next().unwrap()),
-				PgType::DoublePrecision => values.push(f64_iter.next().unwrap()),
-				PgType::Char => values.push(i8_iter.next().unwrap()),
-				PgType::SmallInt => values.push(i16_iter.next().unwrap()),
-				PgType::Integer => values.push(i32_iter.next().unwrap()),
-				PgType::BigInt => values.push(i64_iter.next().unwrap()),
-				PgType::TimeStamp => values.push(ts_iter.next().unwrap()),
-				PgType::IpAddr => values.push(addr_iter.next().unwrap()),
-				PgType::Time => values.push(time_iter.next().unwrap()),
-				PgType::Date => values.push(date_iter.next().unwrap()),
-				PgType::Boolean => {
-					if let serde_json::Value::Bool(b) = pair.1 {
-						values.push(b);
-					} else {
-						panic!("Expected bool, found {}", pair.1);
-					}
-				},
-				PgType::Text => {
-					if let serde_json::Value::String(s) = pair.1 {
-						values.push(s);
-					} else {
-						panic!("Expected string, found {}", pair.1);
-					}
-				},
-				_ => {},
-			};
-		}
-	
-		println!("{}", query);
-		println!("{:?}", values);
-	
-		let rows_affected = self.client.execute(query.as_str(), values.as_slice())?;
+	pub fn delete_full_match<T: Entity>(&mut self, item: &T) -> Result<u64, DbError> {
+		let query = sql::delete_full_match(item)?;
+		let rows_affected = self.client.execute(query.text.as_str(), query.params().as_slice())?;
 		Ok(rows_affected)
 	}
 }
-
-fn unwrap_num(val: &serde_json::value::Value) -> &serde_json::Number {
-	if let serde_json::Value::Number(num) = val {
-		num
-	} else {
-		panic!("Number expected, found {}", val);
-	}
-}
\ No newline at end of file
@@ -1,13 +1,13 @@
 //! Deserialize postgres rows into a Rust data structure.
 use serde::de::{
     self,
-    Deserialize,
     Visitor,
     IntoDeserializer,
     value::SeqDeserializer
 };
 
 use postgres::Row;
+use postgres::types::FromSql;
 
 use crate::error::DeError;
 
@@ -35,7 +35,7 @@ impl Deserializer {
     }
 }
 
-/// Attempt to deserialize from a single `Row`.
+// Attempt to deserialize from a single `Row`.
 // pub fn from_row<'a, T: Deserialize<'a>>(input: Row) -> Result<T> {
 //     let mut deserializer = Deserializer::from_row(input);
 //     Ok(T::deserialize(&mut deserializer)?)
@@ -68,14 +68,39 @@ macro_rules! try_get_from_row {
 }
 
 
+/// Reads the non-null column `name`, which is expected to have the postgres
+/// type `expected`. Used by the code `#[derive(Entity)]` generates.
+pub fn get_column<'a, T: FromSql<'a>>(row: &'a Row, name: &str, expected: &str) -> Result<T> {
+	match get_nullable_column(row, name, expected)? {
+		Some(v) => Ok(v),
+		None => Err(DeError::UnexpectedNull(name.to_string())),
+	}
+}
+
+pub fn get_nullable_column<'a, T: FromSql<'a>>(row: &'a Row, name: &str, expected: &str) -> Result<Option<T>> {
+	let index = match row.columns().iter().position(|c| c.name() == name) {
+		Some(i) => i,
+		None => return Err(DeError::MissingColumn(name.to_string())),
+	};
+	let actual = row.columns()[index].type_();
+	if !<Option<T> as FromSql>::accepts(actual) {
+		return Err(DeError::ColumnType{
+			column: name.to_string(),
+			expected: expected.to_string(),
+			actual: actual.name().to_string(),
+		});
+	}
+	row.try_get(index).map_err(|e| DeError::InvalidType(format!("{}: {}", name, e)))
+}
+
 pub fn from_row<T: for<'de> serde::Deserialize<'de>>(row: Row) -> Result<T> {
 	let mut map = serde_json::map::Map::new();
 	let columns = row.columns();
-	for i in 0..row.len() {
+	for (i, column) in columns.iter().enumerate() {
 		let res = try_get_from_row!(row, i, i32, i64, String, bool, f32, f64, i8, i16, u32, Vec<u8>, SystemTime, IpAddr, Time, Date);
 		match res {
 			Ok(v) => {
-				map.insert(String::from(columns[i].name()), v);
+				map.insert(String::from(column.name()), v);
 			},
 			Err(e) => return std::result::Result::Err(DeError::InvalidType(format!("{:?}", e))),
 		}
@@ -132,7 +157,7 @@ macro_rules! try_get_optional {
 	}};
 }
 
-impl<'de, 'b> de::Deserializer<'de> for &'b mut Deserializer {
+impl<'de> de::Deserializer<'de> for &mut Deserializer {
     type Error = DeError;
 
     unsupported_type! {
@@ -249,7 +274,7 @@ impl<'de, 'b> de::Deserializer<'de> for &'b mut Deserializer {
     }
 }
 
-impl<'de, 'a> de::MapAccess<'de> for Deserializer {
+impl<'de> de::MapAccess<'de> for Deserializer {
     type Error = DeError;
 
     fn next_key_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T)
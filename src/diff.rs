@@ -0,0 +1,369 @@
+//! Compares entity schemes with the tables in the database and plans the
+//! ALTER TABLE statements that bring a table in line with its scheme.
+use std::collections::HashMap;
+
+use rs_pg_scheme::{Action, Constraint, Field, PgType, Scheme, pg_type_name, pg_type_to_str, quote_ident};
+use crate::database::{CreateTableOptions, PostgresClient};
+use crate::entity::Entity;
+use crate::error::DbError;
+use crate::sql;
+
+/// One statement of a migration plan.
+#[derive(Clone, Debug)]
+pub struct MigrationStep {
+	pub sql: String,
+	/// Whether the step can lose data: dropping a column or changing its type.
+	pub destructive: bool,
+}
+
+/// Statements bringing the table of an entity in line with its scheme.
+#[derive(Clone, Debug)]
+pub struct MigrationPlan {
+	pub table: String,
+	pub steps: Vec<MigrationStep>,
+}
+
+impl MigrationPlan {
+	pub fn is_empty(&self) -> bool {
+		self.steps.is_empty()
+	}
+
+	pub fn is_destructive(&self) -> bool {
+		self.steps.iter().any(|s| s.destructive)
+	}
+}
+
+impl std::fmt::Display for MigrationPlan {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		if self.steps.is_empty() {
+			return writeln!(f, "-- {}: up to date", self.table);
+		}
+		for step in self.steps.iter() {
+			if step.destructive {
+				writeln!(f, "{}; -- destructive", step.sql)?;
+			} else {
+				writeln!(f, "{};", step.sql)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Default)]
+pub struct MigrateOptions {
+	/// Print the plan instead of running it.
+	pub dry_run: bool,
+	/// Refuse plans that drop or retype columns.
+	pub safe: bool,
+}
+
+struct LiveColumn {
+	udt_name: String,
+	nullable: bool,
+	default: Option<String>,
+}
+
+/// Check bodies and column defaults of a scheme as the server prints them.
+struct ServerForms {
+	/// By check body.
+	checks: HashMap<String, String>,
+	/// By column name.
+	defaults: HashMap<String, String>,
+}
+
+/// A constraint reduced to what is compared between scheme and database.
+#[derive(PartialEq)]
+enum ConstraintKey {
+	PrimaryKey(String),
+	Unique(String),
+	Check(String),
+	ForeignKey(String, String, String, String, String),
+}
+
+impl PostgresClient {
+	/// Compares the table of `T` with `T::scheme()`. Nothing is changed in
+	/// the database.
+	pub fn plan_migration<T: Entity>(&mut self) -> Result<MigrationPlan, DbError> {
+		let scheme = sql::load_scheme::<T>()?;
+		let table = &scheme.name;
+		let quoted = quote_ident(table);
+		let mut plan = MigrationPlan{
+			table: scheme.name.clone(),
+			steps: vec!(),
+		};
+
+		let exists = self.query("SELECT 1 FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1", &[table])?;
+		if exists.is_empty() {
+			let opts = CreateTableOptions{temp: false, if_not_exists: false};
+			plan.steps.push(MigrationStep{sql: sql::create_table(scheme, &opts), destructive: false});
+			return Ok(plan);
+		}
+
+		let live_columns = self.live_columns(table)?;
+		let live_constraints = self.live_constraints(table)?;
+		let forms = self.server_forms(scheme)?;
+		let expected_constraints = expected_constraints(scheme, &forms.checks);
+
+		// Constraints go first, so dropped and retyped columns are not held
+		// by them, and are added back last, once the columns are in place.
+		for (name, key) in live_constraints.iter() {
+			if !expected_constraints.contains(key) {
+				plan.steps.push(step(format!("ALTER TABLE {} DROP CONSTRAINT {}", quoted, quote_ident(name)), false));
+			}
+		}
+
+		let mut live_names: Vec<&String> = live_columns.keys().collect();
+		live_names.sort();
+		for name in live_names {
+			if scheme.field(name).is_none() {
+				plan.steps.push(step(format!("ALTER TABLE {} DROP COLUMN {}", quoted, quote_ident(name)), true));
+			}
+		}
+
+		for field in scheme.fields.iter() {
+			let name = quote_ident(&field.name);
+			let nullable = !field.constraints.iter().any(|c| matches!(c, Constraint::NotNull | Constraint::PrimaryKey));
+			let live = match live_columns.get(&field.name) {
+				Some(c) => c,
+				None => {
+					let mut query = format!("ALTER TABLE {} ADD COLUMN {} {}", quoted, name, pg_type_to_str(&field.ty));
+					if let Some(expr) = default_expr(field) {
+						query += format!(" DEFAULT {}", expr).as_str();
+					}
+					if !nullable {
+						query += " NOT NULL";
+					}
+					plan.steps.push(step(query, false));
+					continue;
+				}
+			};
+
+			if live.udt_name != udt_name(&field.ty) {
+				let ty = column_type(&field.ty);
+				plan.steps.push(step(format!("ALTER TABLE {0} ALTER COLUMN {1} TYPE {2} USING {1}::{2}", quoted, name, ty), true));
+			}
+			// serial columns default to their sequence, which is not in the scheme
+			if field.ty != PgType::Serial && live.default.as_ref() != forms.defaults.get(&field.name) {
+				let change = match default_expr(field) {
+					Some(expr) => format!("SET DEFAULT {}", expr),
+					None => "DROP DEFAULT".to_string(),
+				};
+				plan.steps.push(step(format!("ALTER TABLE {} ALTER COLUMN {} {}", quoted, name, change), false));
+			}
+			if live.nullable && !nullable {
+				plan.steps.push(step(format!("ALTER TABLE {} ALTER COLUMN {} SET NOT NULL", quoted, name), false));
+			} else if !live.nullable && nullable {
+				plan.steps.push(step(format!("ALTER TABLE {} ALTER COLUMN {} DROP NOT NULL", quoted, name), false));
+			}
+		}
+
+		for (key, definition) in expected_constraints_with_sql(scheme, &forms.checks) {
+			if !live_constraints.iter().any(|(_, k)| *k == key) {
+				plan.steps.push(step(format!("ALTER TABLE {} ADD {}", quoted, definition), false));
+			}
+		}
+		Ok(plan)
+	}
+
+	/// Plans and runs the migration of the table of `T` in one
+	/// transaction. Returns the plan that was run, or would have been run
+	/// with `dry_run`.
+	pub fn auto_migrate<T: Entity>(&mut self, opts: &MigrateOptions) -> Result<MigrationPlan, DbError> {
+		let plan = self.plan_migration::<T>()?;
+		if opts.safe && plan.is_destructive() {
+			return Err(DbError::DestructiveMigration(plan.to_string()));
+		}
+		if opts.dry_run {
+			print!("{}", plan);
+			return Ok(plan);
+		}
+		if plan.is_empty() {
+			return Ok(plan);
+		}
+
+		let mut tx = self.begin()?;
+		for step in plan.steps.iter() {
+			tx.batch_execute(step.sql.as_str())?;
+		}
+		tx.commit()?;
+		Ok(plan)
+	}
+
+	fn live_columns(&mut self, table: &str) -> Result<HashMap<String, LiveColumn>, DbError> {
+		let rows = self.query("SELECT column_name::text, udt_name::text, is_nullable = 'YES', column_default::text FROM information_schema.columns \
+			WHERE table_schema = current_schema() AND table_name = $1", &[&table])?;
+		let mut res = HashMap::new();
+		for row in rows {
+			res.insert(row.get(0), LiveColumn{
+				udt_name: row.get(1),
+				nullable: row.get(2),
+				default: row.get(3),
+			});
+		}
+		Ok(res)
+	}
+
+	fn live_constraints(&mut self, table: &str) -> Result<Vec<(String, ConstraintKey)>, DbError> {
+		let rows = self.query("SELECT c.conname::text, c.contype::text, \
+				(SELECT string_agg(a.attname, ',' ORDER BY a.attname) FROM pg_attribute a WHERE a.attrelid = c.conrelid AND a.attnum = ANY(c.conkey)), \
+				pg_get_constraintdef(c.oid), \
+				(SELECT relname::text FROM pg_class WHERE oid = c.confrelid), \
+				(SELECT string_agg(a.attname, ',' ORDER BY a.attnum) FROM pg_attribute a WHERE a.attrelid = c.confrelid AND a.attnum = ANY(c.confkey)), \
+				c.confdeltype::text, c.confupdtype::text \
+			FROM pg_constraint c JOIN pg_class t ON t.oid = c.conrelid \
+			WHERE t.relname = $1 AND t.relnamespace = current_schema()::regnamespace AND c.contype IN ('p', 'u', 'c', 'f')", &[&table])?;
+		let mut res = vec!();
+		for row in rows {
+			let name: String = row.get(0);
+			let kind: String = row.get(1);
+			let columns: Option<String> = row.get(2);
+			let columns = columns.unwrap_or_default();
+			let key = match kind.as_str() {
+				"p" => ConstraintKey::PrimaryKey(columns),
+				"u" => ConstraintKey::Unique(columns),
+				"c" => ConstraintKey::Check(row.get(3)),
+				_ => {
+					let ref_table: Option<String> = row.get(4);
+					let ref_columns: Option<String> = row.get(5);
+					let on_delete: String = row.get(6);
+					let on_update: String = row.get(7);
+					ConstraintKey::ForeignKey(columns, ref_table.unwrap_or_default(), ref_columns.unwrap_or_default(),
+						live_action(on_delete.as_str()), live_action(on_update.as_str()))
+				}
+			};
+			res.push((name, key));
+		}
+		Ok(res)
+	}
+
+	/// Checks and defaults of `scheme` as the server prints them. The
+	/// server rewrites expressions (casts, parentheses, `IN` lists), so they
+	/// are put on a scratch table with the columns of the scheme, in a
+	/// transaction that is rolled back, and read back from there.
+	fn server_forms(&mut self, scheme: &Scheme) -> Result<ServerForms, DbError> {
+		let bodies: Vec<&String> = scheme.fields.iter()
+			.flat_map(|f| f.constraints.iter())
+			.chain(scheme.constraints.iter())
+			.filter_map(|c| match c {
+				Constraint::Check(body) => Some(body),
+				_ => None,
+			})
+			.collect();
+		let mut res = ServerForms{
+			checks: HashMap::new(),
+			defaults: HashMap::new(),
+		};
+		if bodies.is_empty() && !scheme.fields.iter().any(|f| default_expr(f).is_some()) {
+			return Ok(res);
+		}
+
+		let columns: Vec<String> = scheme.fields.iter().map(|f| match default_expr(f) {
+			Some(expr) => format!("{} {} DEFAULT {}", quote_ident(&f.name), column_type(&f.ty), expr),
+			None => format!("{} {}", quote_ident(&f.name), column_type(&f.ty)),
+		}).collect();
+		let mut tx = self.begin()?;
+		tx.batch_execute(format!("CREATE TEMP TABLE rs_pg_plan_scratch ({})", columns.join(", ")).as_str())?;
+		let defaults = tx.query("SELECT a.attname::text, pg_get_expr(d.adbin, d.adrelid) FROM pg_attrdef d \
+			JOIN pg_attribute a ON a.attrelid = d.adrelid AND a.attnum = d.adnum \
+			WHERE d.adrelid = 'pg_temp.rs_pg_plan_scratch'::regclass", &[])?;
+		for row in defaults {
+			res.defaults.insert(row.get(0), row.get(1));
+		}
+		for body in bodies {
+			tx.batch_execute(format!("ALTER TABLE rs_pg_plan_scratch ADD CONSTRAINT rs_pg_plan_check CHECK ({})", body).as_str())?;
+			let rows = tx.query("SELECT pg_get_constraintdef(oid) FROM pg_constraint \
+				WHERE conrelid = 'pg_temp.rs_pg_plan_scratch'::regclass AND conname = 'rs_pg_plan_check'", &[])?;
+			res.checks.insert(body.clone(), rows[0].get(0));
+			tx.batch_execute("ALTER TABLE rs_pg_plan_scratch DROP CONSTRAINT rs_pg_plan_check")?;
+		}
+		tx.rollback()?;
+		Ok(res)
+	}
+}
+
+fn step(sql: String, destructive: bool) -> MigrationStep {
+	MigrationStep{
+		sql,
+		destructive,
+	}
+}
+
+/// `udt_name` reported by information_schema for a column created with
+/// the type of `ty`.
+fn udt_name(ty: &PgType) -> &'static str {
+	match ty {
+		// created as `char`, which is character(1)
+		PgType::Char => "bpchar",
+		_ => pg_type_name(ty),
+	}
+}
+
+fn column_type(ty: &PgType) -> &'static str {
+	match ty {
+		PgType::Serial => "integer",
+		_ => pg_type_to_str(ty),
+	}
+}
+
+fn live_action(code: &str) -> String {
+	match code {
+		"r" => Action::Restrict,
+		"c" => Action::Cascade,
+		"n" => Action::SetNull,
+		"d" => Action::SetDefault,
+		_ => Action::NoAction,
+	}.to_string()
+}
+
+fn action_name(action: &Option<Action>) -> String {
+	match action {
+		Some(a) => a.to_string(),
+		None => Action::NoAction.to_string(),
+	}
+}
+
+fn default_expr(field: &Field) -> Option<&String> {
+	field.constraints.iter().find_map(|c| match c {
+		Constraint::Default(expr) => Some(expr),
+		_ => None,
+	})
+}
+
+fn expected_constraints(scheme: &Scheme, checks: &HashMap<String, String>) -> Vec<ConstraintKey> {
+	expected_constraints_with_sql(scheme, checks).into_iter().map(|(k, _)| k).collect()
+}
+
+/// `checks` are the check bodies as the server prints them, see
+/// `server_forms`.
+fn expected_constraints_with_sql(scheme: &Scheme, checks: &HashMap<String, String>) -> Vec<(ConstraintKey, String)> {
+	let check = |body: &String| (ConstraintKey::Check(checks[body].clone()), format!("CHECK ({})", body));
+	let mut res = vec!();
+	if !scheme.pk_fields.is_empty() {
+		let names: Vec<&str> = scheme.pk_fields.iter().map(|pk| pk.name.as_str()).collect();
+		let mut columns = names.clone();
+		columns.sort();
+		res.push((ConstraintKey::PrimaryKey(columns.join(",")), format!("PRIMARY KEY ({})", sql::quote_all(&names))));
+	}
+	for field in scheme.fields.iter() {
+		let name = quote_ident(&field.name);
+		let column = &field.name;
+		for constr in field.constraints.iter() {
+			match constr {
+				Constraint::Unique => res.push((ConstraintKey::Unique(column.clone()), format!("UNIQUE ({})", name))),
+				Constraint::Check(body) => res.push(check(body)),
+				Constraint::References(table, ref_column, on_delete, on_update) => {
+					let key = ConstraintKey::ForeignKey(column.clone(), table.clone(), ref_column.clone(),
+						action_name(on_delete), action_name(on_update));
+					res.push((key, format!("FOREIGN KEY ({}) {}", name, constr)));
+				}
+				Constraint::PrimaryKey | Constraint::NotNull | Constraint::Null | Constraint::Default(_) => {}
+			}
+		}
+	}
+	for constr in scheme.constraints.iter() {
+		if let Constraint::Check(body) = constr {
+			res.push(check(body));
+		}
+	}
+	res
+}
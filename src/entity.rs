@@ -1,17 +1,91 @@
+use std::{
+	net::IpAddr,
+	time::SystemTime
+};
+
 use rs_pg_scheme::Scheme;
-use postgres::types::{
-	ToSql,
-	FromSql
+use postgres::Row;
+use postgres::types::ToSql;
+use time::{
+	Time,
+	Date
 };
+use uuid::Uuid;
+
+use crate::error::DeError;
 
 pub trait Entity {
-	fn scheme() -> Scheme;
+	/// Table of the entity. Built once, on first use, by `#[derive(Entity)]`.
+	fn scheme() -> &'static Scheme;
+
+	/// Column names with the values to bind for them, in field order.
+	/// Generated by `#[derive(Entity)]`.
+	fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))>;
+}
+
+/// Builds a value from a result row, reading every field by column name.
+/// Generated by `#[derive(Entity)]`.
+pub trait FromRow: Sized {
+	fn from_row(row: &Row) -> Result<Self, DeError>;
 }
 
-pub trait WithId<'a, T: ToSql + FromSql<'a>> {
-	fn __get_pk(&self) -> &T;
-	fn __set_pk(&mut self, v: T);
-	fn __borrow_pk(&self) -> T;
+/// Value of a primary key: a single column type, or a tuple of them for
+/// composite keys.
+pub trait PrimaryKey: Sized {
+	fn values(&self) -> Vec<&(dyn ToSql + Sync)>;
+
+	/// Reads the key from the leading columns of `row`, e.g. the ones
+	/// returned by `INSERT ... RETURNING`.
+	fn from_row(row: &Row) -> Result<Self, DeError>;
+}
+
+fn get_key_column<'a, T: postgres::types::FromSql<'a>>(row: &'a Row, index: usize) -> Result<T, DeError> {
+	row.try_get(index).map_err(|e| DeError::InvalidType(format!("key column {}: {}", index, e)))
+}
 
-//	fn __assign_id_from_
-}
\ No newline at end of file
+macro_rules! single_primary_key {
+	($($ty:ty),*) => {
+		$(
+			impl PrimaryKey for $ty {
+				fn values(&self) -> Vec<&(dyn ToSql + Sync)> {
+					vec!(self)
+				}
+
+				fn from_row(row: &Row) -> Result<Self, DeError> {
+					get_key_column(row, 0)
+				}
+			}
+		)*
+	}
+}
+
+single_primary_key!(i8, i16, i32, i64, bool, String, Vec<u8>, SystemTime, IpAddr, Date, Time, Uuid);
+
+macro_rules! tuple_primary_key {
+	($($name:ident $index:tt),*) => {
+		impl<$($name),*> PrimaryKey for ($($name,)*)
+		where $($name: ToSql + Sync + for<'a> postgres::types::FromSql<'a>),* {
+			fn values(&self) -> Vec<&(dyn ToSql + Sync)> {
+				vec!($(&self.$index),*)
+			}
+
+			fn from_row(row: &Row) -> Result<Self, DeError> {
+				Ok(($(get_key_column(row, $index)?,)*))
+			}
+		}
+	}
+}
+
+tuple_primary_key!(A 0, B 1);
+tuple_primary_key!(A 0, B 1, C 2);
+tuple_primary_key!(A 0, B 1, C 2, D 3);
+
+/// Access to the primary key of an entity. Generated by `#[derive(Entity)]`
+/// for structs with `#[primary_key]` fields; `K` is the field type, or a
+/// tuple of the field types for composite keys.
+pub trait WithId<K: PrimaryKey> {
+	/// The key columns, in key order.
+	fn __get_pk(&self) -> Vec<&(dyn ToSql + Sync)>;
+	fn __set_pk(&mut self, v: K);
+	fn __borrow_pk(&self) -> K;
+}
@@ -2,9 +2,80 @@
 use std::fmt;
 use serde::{de, ser};
 use thiserror::Error;
+use std::error::Error as _;
+use postgres::error::SqlState;
+use rs_pg_scheme::{Constraint, Scheme};
 
 impl From<postgres::Error> for DbError {
-    fn from(e: postgres::Error) -> Self { DbError::PostgresError(e) }
+    fn from(e: postgres::Error) -> Self {
+        let info = Box::new(ErrorInfo::from(&e));
+        if e.is_closed() || e.source().is_some_and(|s| s.is::<std::io::Error>()) {
+            return DbError::ConnectionLost(info);
+        }
+        let code = match e.code() {
+            Some(code) => code,
+            None => return DbError::PostgresError(e),
+        };
+        if *code == SqlState::UNIQUE_VIOLATION {
+            DbError::UniqueViolation(info)
+        } else if *code == SqlState::FOREIGN_KEY_VIOLATION {
+            DbError::ForeignKeyViolation(info)
+        } else if *code == SqlState::CHECK_VIOLATION {
+            DbError::CheckViolation(info)
+        } else if *code == SqlState::NOT_NULL_VIOLATION {
+            DbError::NotNullViolation(info)
+        } else if *code == SqlState::T_R_SERIALIZATION_FAILURE {
+            DbError::SerializationFailure(info)
+        } else if *code == SqlState::T_R_DEADLOCK_DETECTED {
+            DbError::Deadlock(info)
+        } else if code.code().starts_with("08") || *code == SqlState::ADMIN_SHUTDOWN || *code == SqlState::CRASH_SHUTDOWN {
+            DbError::ConnectionLost(info)
+        } else {
+            DbError::PostgresError(e)
+        }
+    }
+}
+
+/// What the server reported about a failed statement.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorInfo {
+    pub message: String,
+    pub constraint: Option<String>,
+    pub table: Option<String>,
+    pub column: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl From<&postgres::Error> for ErrorInfo {
+    fn from(e: &postgres::Error) -> Self {
+        match e.as_db_error() {
+            Some(db) => ErrorInfo{
+                message: db.message().to_string(),
+                constraint: db.constraint().map(str::to_string),
+                table: db.table().map(str::to_string),
+                column: db.column().map(str::to_string),
+                detail: db.detail().map(str::to_string),
+            },
+            None => ErrorInfo{
+                message: e.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for ErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<r2d2::Error> for DbError {
+	fn from(e: r2d2::Error) -> Self { DbError::PoolError(e) }
 }
 
 impl From<DeError> for DbError {
@@ -19,10 +90,108 @@ pub enum DbError {
     DataError(DataError),
     #[error("{0}")]
     PostgresError(postgres::Error),
+    #[error("Unique violation: {0}")]
+    UniqueViolation(Box<ErrorInfo>),
+    #[error("Foreign key violation: {0}")]
+    ForeignKeyViolation(Box<ErrorInfo>),
+    #[error("Check violation: {0}")]
+    CheckViolation(Box<ErrorInfo>),
+    #[error("Not null violation: {0}")]
+    NotNullViolation(Box<ErrorInfo>),
+    #[error("Serialization failure: {0}")]
+    SerializationFailure(Box<ErrorInfo>),
+    #[error("Deadlock detected: {0}")]
+    Deadlock(Box<ErrorInfo>),
+    #[error("Connection lost: {0}")]
+    ConnectionLost(Box<ErrorInfo>),
     #[error("{0}")]
     ConvertError(DeError),
     #[error("Unsupported operation: {0}")]
     UnsupportedOperation(String),
+    #[error("Can't send entity: {0}")]
+    SerializationError(String),
+    #[error("Field {field} can't be sent as {expected}")]
+    FieldTypeMismatch { field: String, expected: String },
+    #[error("Unknown column: {0}")]
+    UnknownColumn(String),
+    #[error("Invalid connection settings: {0}")]
+    InvalidConfig(String),
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    #[error("Connection pool error: {0}")]
+    PoolError(r2d2::Error),
+    #[error("Migration would drop or retype columns:\n{0}")]
+    DestructiveMigration(String),
+    #[error("Tables reference each other in a cycle: {0:?}")]
+    ReferenceCycle(Vec<String>),
+    #[error("Invalid page cursor: {0}")]
+    InvalidCursor(String),
+    #[error("Migration error: {0}")]
+    MigrationError(String),
+    #[error("Gave up after {attempts} attempts: {source}")]
+    RetriesExhausted { attempts: u32, source: Box<DbError> },
+}
+
+impl DbError {
+    /// Whether the server aborted the transaction with a serialization
+    /// failure (40001) or a deadlock (40P01), so running it again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DbError::SerializationFailure(_) | DbError::Deadlock(_))
+    }
+
+    /// Server-side details of constraint violations, serialization
+    /// failures, deadlocks and lost connections.
+    pub fn info(&self) -> Option<&ErrorInfo> {
+        match self {
+            DbError::UniqueViolation(i) | DbError::ForeignKeyViolation(i) | DbError::CheckViolation(i)
+            | DbError::NotNullViolation(i) | DbError::SerializationFailure(i) | DbError::Deadlock(i)
+            | DbError::ConnectionLost(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// The constraint of `scheme` that was violated. Constraints are found
+    /// by the names postgres gives them by default, e.g. `persons_first_name_key`.
+    pub fn violated_constraint(&self, scheme: &Scheme) -> Option<Constraint> {
+        let table = &scheme.name;
+        match self {
+            DbError::NotNullViolation(info) => {
+                let column = info.column.as_deref()?;
+                scheme.fields.iter()
+                    .find(|f| f.name == column)?
+                    .constraints.iter()
+                    .find(|c| matches!(c, Constraint::NotNull))
+                    .cloned()
+            }
+            DbError::UniqueViolation(info) | DbError::ForeignKeyViolation(info) | DbError::CheckViolation(info) => {
+                let name = info.constraint.as_deref()?;
+                if matches!(self, DbError::UniqueViolation(_)) && name == format!("{}_pkey", table) {
+                    return Some(Constraint::PrimaryKey);
+                }
+                for field in scheme.fields.iter() {
+                    let prefix = format!("{}_{}", table, field.name);
+                    for constr in field.constraints.iter() {
+                        let matched = match constr {
+                            Constraint::Unique => name == format!("{}_key", prefix),
+                            Constraint::References(..) => name == format!("{}_fkey", prefix),
+                            Constraint::Check(_) => name == format!("{}_check", prefix),
+                            _ => false,
+                        };
+                        if matched {
+                            return Some(constr.clone());
+                        }
+                    }
+                }
+                // table checks are named `table_check`, `table_check1`, ...
+                scheme.constraints.iter()
+                    .filter(|c| matches!(c, Constraint::Check(_)))
+                    .enumerate()
+                    .find(|(i, _)| if *i == 0 { name == format!("{}_check", table) } else { name == format!("{}_check{}", table, i) })
+                    .map(|(_, c)| c.clone())
+            }
+            _ => None,
+        }
+    }
 }
 
 /// This type represents all possible error that can occur when deserializing
@@ -41,6 +210,15 @@ pub enum DeError {
     /// Rust data structure contained a type unsupported by `serde_postgres`.
 	#[error("Type unsupported")]
 	UnsupportedType,
+    /// Row has no column for a field of the data structure.
+	#[error("Missing column {0}")]
+	MissingColumn(String),
+    /// Column was NULL but the field is not an `Option`.
+	#[error("Column {0} is NULL but the field is not optional")]
+	UnexpectedNull(String),
+    /// Column's type can't be read into the field.
+	#[error("Column {column} has type {actual}, expected {expected}")]
+	ColumnType { column: String, expected: String, actual: String },
 }
 
 impl de::Error for DeError {
@@ -1,16 +1,57 @@
 pub mod database;
-pub use database::{PostgresClient, CreateTableOptions, ConnectOptions};
+pub use database::{PostgresClient, CreateTableOptions, ConnectOptions, UpsertOptions, ConflictTarget, ConflictAction};
+
+pub mod config;
+pub use config::TargetSessionAttrs;
 
 pub mod entity;
-pub use entity::{Entity, WithId};
+pub use entity::{Entity, FromRow, WithId};
 
 extern crate serde;
 extern crate postgres;
 extern crate rs_pg_scheme;
 
+pub mod async_client;
+pub use async_client::AsyncPostgresClient;
+
+pub mod tls;
+pub use tls::SslMode;
+
+pub mod pool;
+pub use pool::{PostgresPool, PoolOptions, PooledClient};
+
+pub mod transaction;
+pub use transaction::{Transaction, TransactionOptions, IsolationLevel};
+
+pub mod retry;
+pub use retry::{RetryPolicy, RetryReport};
+
+pub mod query;
+pub use query::{Query, Select, Filter, Order};
+
+pub mod cursor;
+pub use cursor::SelectIter;
+
+pub mod page;
+pub use page::{Page, PageRequest, OffsetPageRequest};
+
+pub mod diff;
+pub use diff::{MigrationPlan, MigrationStep, MigrateOptions};
+
+pub mod migration;
+pub use migration::{Migration, Migrations, MigrationReport};
+
+pub mod registry;
+pub use registry::{SchemaRegistry, DropMode};
+
+mod sql;
+
 pub mod de;
 pub mod error;
 
 pub use de::{from_row, Deserializer};
-pub use error::{DbError};//, Result};
-pub use rs_pg_scheme::{Serial, Scheme};
\ No newline at end of file
+pub use error::{DbError, ErrorInfo};//, Result};
+pub use rs_pg_scheme::{Serial, Scheme, Field, PkField, PgType, Constraint, Action};
+pub use postgres::Row;
+pub use postgres::types::ToSql;
+pub use uuid::Uuid;
\ No newline at end of file
@@ -0,0 +1,248 @@
+//! Versioned migrations, recorded in the `_rs_pg_migrations` table.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::database::PostgresClient;
+use crate::error::DbError;
+use crate::transaction::Transaction;
+
+const HISTORY_TABLE: &str = "_rs_pg_migrations";
+/// Key of the advisory lock held while migrating ("rs_pg_mi").
+const LOCK_KEY: i64 = 0x72735f70675f6d69;
+
+type MigrationFn = Box<dyn Fn(&mut Transaction<'_>) -> Result<(), DbError>>;
+
+enum Step {
+	Sql(String),
+	Rust(MigrationFn),
+}
+
+/// A numbered schema change with an optional way back.
+pub struct Migration {
+	pub version: i64,
+	pub name: String,
+	up: Step,
+	down: Option<Step>,
+}
+
+impl Migration {
+	pub fn sql(version: i64, name: &str, up: &str) -> Migration {
+		Migration{
+			version,
+			name: name.to_string(),
+			up: Step::Sql(up.to_string()),
+			down: None,
+		}
+	}
+
+	/// A migration written in Rust. It runs inside the migration's
+	/// transaction, and has no checksum, so changes to it go unnoticed.
+	pub fn rust<F>(version: i64, name: &str, up: F) -> Migration
+	where F: Fn(&mut Transaction<'_>) -> Result<(), DbError> + 'static {
+		Migration{
+			version,
+			name: name.to_string(),
+			up: Step::Rust(Box::new(up)),
+			down: None,
+		}
+	}
+
+	pub fn down_sql(mut self, down: &str) -> Self {
+		self.down = Some(Step::Sql(down.to_string()));
+		self
+	}
+
+	pub fn down_rust<F>(mut self, down: F) -> Self
+	where F: Fn(&mut Transaction<'_>) -> Result<(), DbError> + 'static {
+		self.down = Some(Step::Rust(Box::new(down)));
+		self
+	}
+
+	/// FNV-1a of the up script, `None` for Rust migrations.
+	fn checksum(&self) -> Option<i64> {
+		match &self.up {
+			Step::Sql(sql) => Some(fnv1a(sql.as_bytes()) as i64),
+			Step::Rust(_) => None,
+		}
+	}
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+	let mut hash: u64 = 0xcbf29ce484222325;
+	for b in bytes {
+		hash ^= *b as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+fn run_step(tx: &mut Transaction<'_>, step: &Step) -> Result<(), DbError> {
+	match step {
+		Step::Sql(sql) => tx.batch_execute(sql.as_str()),
+		Step::Rust(f) => f(tx),
+	}
+}
+
+#[derive(Default)]
+pub struct Migrations {
+	list: Vec<Migration>,
+}
+
+impl Migrations {
+	pub fn new() -> Migrations {
+		Migrations::default()
+	}
+
+	pub fn with(mut self, migration: Migration) -> Self {
+		self.list.push(migration);
+		self
+	}
+
+	/// Reads `<version>_<name>.up.sql` files, with their optional
+	/// `<version>_<name>.down.sql` counterparts, from `dir`.
+	pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Migrations, DbError> {
+		let read_err = |e: &dyn std::fmt::Display| DbError::MigrationError(format!("{}: {}", dir.as_ref().display(), e));
+
+		let mut ups = HashMap::new();
+		let mut downs = HashMap::new();
+		for entry in fs::read_dir(dir.as_ref()).map_err(|e| read_err(&e))? {
+			let path = entry.map_err(|e| read_err(&e))?.path();
+			let file_name = match path.file_name().and_then(|n| n.to_str()) {
+				Some(n) => n.to_string(),
+				None => continue,
+			};
+			let (stem, is_up) = if let Some(stem) = file_name.strip_suffix(".up.sql") {
+				(stem.to_string(), true)
+			} else if let Some(stem) = file_name.strip_suffix(".down.sql") {
+				(stem.to_string(), false)
+			} else {
+				continue;
+			};
+			let sql = fs::read_to_string(&path).map_err(|e| read_err(&e))?;
+			if is_up {
+				ups.insert(stem, sql);
+			} else {
+				downs.insert(stem, sql);
+			}
+		}
+
+		let mut res = Migrations::new();
+		for (stem, up) in ups {
+			let (version, name) = match stem.split_once('_') {
+				Some((v, n)) => (v, n),
+				None => (stem.as_str(), ""),
+			};
+			let version = version.parse().map_err(|_| DbError::MigrationError(format!("{}: file name does not start with a version", stem)))?;
+			let mut migration = Migration::sql(version, name, up.as_str());
+			if let Some(down) = downs.remove(&stem) {
+				migration = migration.down_sql(down.as_str());
+			}
+			res.list.push(migration);
+		}
+		if let Some(stem) = downs.keys().next() {
+			return Err(DbError::MigrationError(format!("{}.down.sql has no up script", stem)));
+		}
+		Ok(res)
+	}
+
+	fn sorted(&self) -> Result<Vec<&Migration>, DbError> {
+		let mut list: Vec<&Migration> = self.list.iter().collect();
+		list.sort_by_key(|m| m.version);
+		for pair in list.windows(2) {
+			if pair[0].version == pair[1].version {
+				return Err(DbError::MigrationError(format!("version {} is used twice", pair[0].version)));
+			}
+		}
+		Ok(list)
+	}
+}
+
+/// Outcome of `PostgresClient::migrate`.
+#[derive(Clone, Debug, Default)]
+pub struct MigrationReport {
+	/// Versions applied by this run, in order.
+	pub applied: Vec<i64>,
+	/// Versions applied earlier whose SQL has changed since.
+	pub changed: Vec<i64>,
+}
+
+impl PostgresClient {
+	/// Applies the migrations that have not been applied yet, in version
+	/// order, each in its own transaction.
+	pub fn migrate(&mut self, migrations: &Migrations) -> Result<MigrationReport, DbError> {
+		self.with_migration_lock(|client| {
+			let applied = client.applied_migrations()?;
+			let mut res = MigrationReport::default();
+			for migration in migrations.sorted()? {
+				if let Some(checksum) = applied.get(&migration.version) {
+					if checksum.is_some() && *checksum != migration.checksum() {
+						res.changed.push(migration.version);
+					}
+					continue;
+				}
+
+				let mut tx = client.begin()?;
+				run_step(&mut tx, &migration.up)?;
+				tx.execute(format!("INSERT INTO {}(version, name, checksum) VALUES ($1, $2, $3)", HISTORY_TABLE).as_str(),
+					&[&migration.version, &migration.name, &migration.checksum()])?;
+				tx.commit()?;
+				res.applied.push(migration.version);
+			}
+			Ok(res)
+		})
+	}
+
+	/// Reverts the applied migrations newer than `version`, newest first.
+	/// Returns the reverted versions.
+	pub fn rollback_to(&mut self, migrations: &Migrations, version: i64) -> Result<Vec<i64>, DbError> {
+		self.with_migration_lock(|client| {
+			let mut applied: Vec<i64> = client.applied_migrations()?.into_keys()
+				.filter(|v| *v > version)
+				.collect();
+			applied.sort_unstable_by(|a, b| b.cmp(a));
+
+			let list = migrations.sorted()?;
+			let mut res = vec!();
+			for v in applied {
+				let migration = match list.iter().find(|m| m.version == v) {
+					Some(m) => m,
+					None => return Err(DbError::MigrationError(format!("applied migration {} is unknown", v))),
+				};
+				let down = match &migration.down {
+					Some(d) => d,
+					None => return Err(DbError::MigrationError(format!("migration {} ({}) can't be reverted", v, migration.name))),
+				};
+
+				let mut tx = client.begin()?;
+				run_step(&mut tx, down)?;
+				tx.execute(format!("DELETE FROM {} WHERE version = $1", HISTORY_TABLE).as_str(), &[&v])?;
+				tx.commit()?;
+				res.push(v);
+			}
+			Ok(res)
+		})
+	}
+
+	/// Runs `f` holding the advisory lock, so concurrent runners wait for
+	/// each other instead of applying the same migrations twice.
+	fn with_migration_lock<F, R>(&mut self, f: F) -> Result<R, DbError>
+	where F: FnOnce(&mut PostgresClient) -> Result<R, DbError> {
+		self.query("SELECT pg_advisory_lock($1)", &[&LOCK_KEY])?;
+		let res = self.batch_execute(format!("CREATE TABLE IF NOT EXISTS {}(\
+				version bigint PRIMARY KEY, \
+				name text NOT NULL, \
+				checksum bigint, \
+				applied_at timestamptz NOT NULL DEFAULT now())", HISTORY_TABLE).as_str())
+			.and_then(|_| f(self));
+		let unlock = self.query("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY]);
+		let res = res?;
+		unlock?;
+		Ok(res)
+	}
+
+	fn applied_migrations(&mut self) -> Result<HashMap<i64, Option<i64>>, DbError> {
+		let rows = self.query(format!("SELECT version, checksum FROM {}", HISTORY_TABLE).as_str(), &[])?;
+		Ok(rows.iter().map(|r| (r.get(0), r.get(1))).collect())
+	}
+}
@@ -0,0 +1,351 @@
+//! Keyset and offset pagination.
+use postgres::Row;
+use postgres::types::ToSql;
+
+use rs_pg_scheme::{Constraint, Scheme, pg_type_name, quote_ident};
+use crate::async_client::AsyncPostgresClient;
+use crate::database::PostgresClient;
+use crate::entity::{Entity, FromRow};
+use crate::error::DbError;
+use crate::query::{Filter, Order};
+use crate::sql::{check_column, load_scheme, quote_all};
+
+/// A page of keyset pagination: `size` rows following the row that
+/// `after` points at, ordered by `order_by` and then by the primary key.
+/// The primary key alone is used if `order_by` is `None`.
+#[derive(Clone)]
+pub struct PageRequest {
+	pub size: i64,
+	/// `next` of the previous page, `None` for the first one.
+	pub after: Option<String>,
+	pub order_by: Option<String>,
+	pub order: Order,
+	/// Also count the rows matching the filter.
+	pub with_total: bool,
+}
+
+impl Default for PageRequest {
+	fn default() -> Self {
+		PageRequest{
+			size: 50,
+			after: None,
+			order_by: None,
+			order: Order::Asc,
+			with_total: false,
+		}
+	}
+}
+
+/// Page `page` (counted from 0) of `size` rows, read with OFFSET.
+#[derive(Clone)]
+pub struct OffsetPageRequest {
+	pub page: i64,
+	pub size: i64,
+	pub order_by: Option<String>,
+	pub order: Order,
+	pub with_total: bool,
+}
+
+impl Default for OffsetPageRequest {
+	fn default() -> Self {
+		OffsetPageRequest{
+			page: 0,
+			size: 50,
+			order_by: None,
+			order: Order::Asc,
+			with_total: false,
+		}
+	}
+}
+
+pub struct Page<T> {
+	pub items: Vec<T>,
+	/// Cursor of the next page, `None` on the last one. For offset pages it
+	/// is the number of the next page.
+	pub next: Option<String>,
+	pub total: Option<i64>,
+}
+
+/// Statements of one page, the params of `select` come first in `params`.
+struct PageSql<'a> {
+	select: String,
+	count: Option<String>,
+	params: Vec<&'a (dyn ToSql + Sync)>,
+	filter_params: usize,
+	size: i64,
+	/// Number of cursor columns appended to every row, 0 for offset pages.
+	cursor_columns: usize,
+}
+
+impl PostgresClient {
+	/// Reads the page of rows matching `filter` that follows `req.after`.
+	pub fn page<T>(&mut self, filter: Option<Filter>, req: &PageRequest) -> Result<Page<T>, DbError>
+	where T: Entity + FromRow {
+		let after = decode_cursor(&req.after)?;
+		let sql = keyset_sql::<T>(&filter, req, &after)?;
+		let rows = self.query(sql.select.as_str(), sql.params.as_slice())?;
+		let total = match &sql.count {
+			Some(count) => Some(self.query(count.as_str(), &sql.params[..sql.filter_params])?[0].get(0)),
+			None => None,
+		};
+		keyset_page(rows, &sql, total)
+	}
+
+	pub fn page_offset<T>(&mut self, filter: Option<Filter>, req: &OffsetPageRequest) -> Result<Page<T>, DbError>
+	where T: Entity + FromRow {
+		let sql = offset_sql::<T>(&filter, req)?;
+		let rows = self.query(sql.select.as_str(), sql.params.as_slice())?;
+		let total = match &sql.count {
+			Some(count) => Some(self.query(count.as_str(), &sql.params[..sql.filter_params])?[0].get(0)),
+			None => None,
+		};
+		offset_page(rows, &sql, req.page, total)
+	}
+}
+
+impl AsyncPostgresClient {
+	pub async fn page<T>(&self, filter: Option<Filter>, req: &PageRequest) -> Result<Page<T>, DbError>
+	where T: Entity + FromRow {
+		let after = decode_cursor(&req.after)?;
+		let sql = keyset_sql::<T>(&filter, req, &after)?;
+		let rows = self.query(sql.select.as_str(), sql.params.as_slice()).await?;
+		let total = match &sql.count {
+			Some(count) => Some(self.query(count.as_str(), &sql.params[..sql.filter_params]).await?[0].get(0)),
+			None => None,
+		};
+		keyset_page(rows, &sql, total)
+	}
+
+	pub async fn page_offset<T>(&self, filter: Option<Filter>, req: &OffsetPageRequest) -> Result<Page<T>, DbError>
+	where T: Entity + FromRow {
+		let sql = offset_sql::<T>(&filter, req)?;
+		let rows = self.query(sql.select.as_str(), sql.params.as_slice()).await?;
+		let total = match &sql.count {
+			Some(count) => Some(self.query(count.as_str(), &sql.params[..sql.filter_params]).await?[0].get(0)),
+			None => None,
+		};
+		offset_page(rows, &sql, req.page, total)
+	}
+}
+
+/// The cursor is a JSON array with the text of every keyset column.
+fn decode_cursor(after: &Option<String>) -> Result<Option<Vec<String>>, DbError> {
+	match after {
+		Some(cursor) => serde_json::from_str(cursor)
+			.map(Some)
+			.map_err(|_| DbError::InvalidCursor(cursor.clone())),
+		None => Ok(None),
+	}
+}
+
+/// Sort column followed by the primary key columns, without duplicates.
+fn keyset_columns(scheme: &Scheme, order_by: &Option<String>) -> Result<Vec<String>, DbError> {
+	let mut columns = vec!();
+	if let Some(column) = order_by {
+		check_column(scheme, column)?;
+		columns.push(column.clone());
+	}
+	for pk in scheme.pk_fields.iter() {
+		if !columns.contains(&pk.name) {
+			columns.push(pk.name.clone());
+		}
+	}
+	if columns.is_empty() {
+		return Err(DbError::UnsupportedOperation(format!("{} has no primary key to page by", scheme.name)));
+	}
+	Ok(columns)
+}
+
+/// `FROM table WHERE filter`, with the filter params pushed to `params`.
+fn from_where<'a>(scheme: &Scheme, filter: &'a Option<Filter>, params: &mut Vec<&'a (dyn ToSql + Sync)>) -> Result<String, DbError> {
+	let mut query = format!(" FROM {}", quote_ident(&scheme.name));
+	if let Some(f) = filter {
+		query += " WHERE ";
+		f.write_sql(scheme, &mut query, params)?;
+	}
+	Ok(query)
+}
+
+fn order_by(columns: &[String], order: Order) -> String {
+	let columns: Vec<String> = columns.iter().map(|c| format!("{} {}", quote_ident(c), order)).collect();
+	format!(" ORDER BY {}", columns.join(", "))
+}
+
+/// LIMIT of a page of `size` rows, one more row tells whether there is a
+/// next page.
+fn page_limit(size: i64) -> Result<i64, DbError> {
+	if size <= 0 {
+		return Err(DbError::UnsupportedOperation("page size must be positive".to_string()));
+	}
+	size.checked_add(1).ok_or_else(|| DbError::UnsupportedOperation(format!("page size {} is too large", size)))
+}
+
+fn keyset_sql<'a, T: Entity>(filter: &'a Option<Filter>, req: &PageRequest, after: &'a Option<Vec<String>>) -> Result<PageSql<'a>, DbError> {
+	let limit = page_limit(req.size)?;
+	let scheme = load_scheme::<T>()?;
+	let columns = keyset_columns(scheme, &req.order_by)?;
+	for column in columns.iter() {
+		if scheme.field(column).is_some_and(|f| f.constraints.iter().any(|c| matches!(c, Constraint::Null))) {
+			return Err(DbError::UnsupportedOperation(format!("can't page by nullable column {}", column)));
+		}
+	}
+
+	let mut params = vec!();
+	let from = from_where(scheme, filter, &mut params)?;
+	let filter_params = params.len();
+	let count = if req.with_total { Some(format!("SELECT COUNT(*){}", from)) } else { None };
+
+	let cursor: Vec<String> = (0..columns.len()).map(|i| format!("{}::text AS __rs_pg_cursor_{}", quote_ident(&columns[i]), i)).collect();
+	let mut select = format!("SELECT *, {}{}", cursor.join(", "), from);
+
+	if let Some(values) = after {
+		if values.len() != columns.len() {
+			return Err(DbError::InvalidCursor(req.after.clone().unwrap_or_default()));
+		}
+		let mut bounds = vec!();
+		for (column, value) in columns.iter().zip(values.iter()) {
+			params.push(value);
+			let ty = pg_type_name(&scheme.field(column).unwrap().ty);
+			bounds.push(format!("${}::text::{}", params.len(), ty));
+		}
+		let op = if req.order == Order::Asc { ">" } else { "<" };
+		select += if filter.is_some() { " AND " } else { " WHERE " };
+		select += format!("({}) {} ({})", quote_all(&columns), op, bounds.join(", ")).as_str();
+	}
+
+	select += order_by(&columns, req.order).as_str();
+	select += format!(" LIMIT {}", limit).as_str();
+	Ok(PageSql{
+		select,
+		count,
+		params,
+		filter_params,
+		size: req.size,
+		cursor_columns: columns.len(),
+	})
+}
+
+fn offset_sql<'a, T: Entity>(filter: &'a Option<Filter>, req: &OffsetPageRequest) -> Result<PageSql<'a>, DbError> {
+	let limit = page_limit(req.size)?;
+	if req.page < 0 {
+		return Err(DbError::UnsupportedOperation("page number must not be negative".to_string()));
+	}
+	let offset = req.page.checked_mul(req.size)
+		.filter(|offset| offset.checked_add(limit).is_some())
+		.ok_or_else(|| DbError::UnsupportedOperation(format!("page {} of {} rows is out of range", req.page, req.size)))?;
+	let scheme = load_scheme::<T>()?;
+	let columns = keyset_columns(scheme, &req.order_by)?;
+
+	let mut params = vec!();
+	let from = from_where(scheme, filter, &mut params)?;
+	let count = if req.with_total { Some(format!("SELECT COUNT(*){}", from)) } else { None };
+	let select = format!("SELECT *{}{} LIMIT {} OFFSET {}", from, order_by(&columns, req.order), limit, offset);
+	Ok(PageSql{
+		select,
+		count,
+		filter_params: params.len(),
+		params,
+		size: req.size,
+		cursor_columns: 0,
+	})
+}
+
+/// Decodes up to `size` rows, the extra one fetched tells whether there is
+/// a next page.
+fn page_items<T: FromRow>(mut rows: Vec<Row>, sql: &PageSql) -> Result<(Vec<T>, Option<Row>), DbError> {
+	let has_next = rows.len() as i64 > sql.size;
+	rows.truncate(sql.size as usize);
+	let mut items = vec!();
+	for row in rows.iter() {
+		items.push(T::from_row(row)?);
+	}
+	let last = if has_next { rows.pop() } else { None };
+	Ok((items, last))
+}
+
+fn keyset_page<T: FromRow>(rows: Vec<Row>, sql: &PageSql, total: Option<i64>) -> Result<Page<T>, DbError> {
+	let first_cursor = rows.first().map(|r| r.len() - sql.cursor_columns).unwrap_or_default();
+	let (items, last) = page_items(rows, sql)?;
+	let next = match last {
+		Some(row) => {
+			let mut values: Vec<String> = vec!();
+			for i in 0..sql.cursor_columns {
+				values.push(row.try_get(first_cursor + i)?);
+			}
+			Some(serde_json::to_string(&values).unwrap())
+		}
+		None => None,
+	};
+	Ok(Page{
+		items,
+		next,
+		total,
+	})
+}
+
+fn offset_page<T: FromRow>(rows: Vec<Row>, sql: &PageSql, page: i64, total: Option<i64>) -> Result<Page<T>, DbError> {
+	let (items, last) = page_items(rows, sql)?;
+	Ok(Page{
+		items,
+		next: last.map(|_| (page + 1).to_string()),
+		total,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::OnceLock;
+	use rs_pg_scheme::{Field, PgType, PkField};
+
+	struct Item {
+		id: i32,
+	}
+
+	impl Entity for Item {
+		fn scheme() -> &'static Scheme {
+			static SCHEME: OnceLock<Scheme> = OnceLock::new();
+			SCHEME.get_or_init(|| Scheme{
+				name: "items".to_string(),
+				pk_fields: vec!(PkField{name: "id".to_string(), ty: PgType::Serial}),
+				fields: vec!(Field{name: "id".to_string(), ty: PgType::Serial, constraints: vec!(Constraint::NotNull, Constraint::PrimaryKey)}),
+				constraints: vec!(),
+			})
+		}
+
+		fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))> {
+			vec!(("id", &self.id))
+		}
+	}
+
+	fn assert_unsupported(res: Result<PageSql, DbError>) {
+		match res {
+			Err(DbError::UnsupportedOperation(_)) => {}
+			Err(e) => panic!("unexpected error {}", e),
+			Ok(sql) => panic!("would run {}", sql.select),
+		}
+	}
+
+	#[test]
+	fn pages_have_one_extra_row() {
+		let req = OffsetPageRequest{page: 2, size: 10, ..Default::default()};
+		let sql = offset_sql::<Item>(&None, &req).unwrap();
+		assert_eq!(sql.select, r#"SELECT * FROM "items" ORDER BY "id" ASC LIMIT 11 OFFSET 20"#);
+
+		let req = PageRequest{size: 10, ..Default::default()};
+		let sql = keyset_sql::<Item>(&None, &req, &None).unwrap();
+		assert!(sql.select.ends_with(" LIMIT 11"));
+	}
+
+	#[test]
+	fn refuses_sizes_and_pages_out_of_range() {
+		for size in [0, -1, i64::MAX] {
+			assert_unsupported(keyset_sql::<Item>(&None, &PageRequest{size, ..Default::default()}, &None));
+			assert_unsupported(offset_sql::<Item>(&None, &OffsetPageRequest{size, ..Default::default()}));
+		}
+		for (page, size) in [(-1, 10), (i64::MAX, 2), (i64::MAX / 2, 2), (i64::MAX, 1)] {
+			assert_unsupported(offset_sql::<Item>(&None, &OffsetPageRequest{page, size, ..Default::default()}));
+		}
+		offset_sql::<Item>(&None, &OffsetPageRequest{page: i64::MAX / 4, size: 2, ..Default::default()}).unwrap();
+	}
+}
@@ -0,0 +1,195 @@
+//! Connection pool handing out `PostgresClient`s.
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use crate::database::{PostgresClient, ConnectOptions};
+use crate::error::DbError;
+use crate::tls::Connector;
+
+#[derive(Clone)]
+pub struct PoolOptions {
+	/// Idle connections kept open, and opened when the pool is created.
+	/// Defaults to 1; `None` keeps `max_size` connections open.
+	pub min_idle: Option<u32>,
+	pub max_size: u32,
+	/// Idle connections older than this are closed.
+	pub idle_timeout: Option<Duration>,
+	/// Connections older than this are closed once returned to the pool.
+	pub max_lifetime: Option<Duration>,
+	/// Run a health check before a connection is handed out.
+	pub test_on_checkout: bool,
+	/// How long `get` waits for a free connection.
+	pub checkout_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+	fn default() -> Self {
+		PoolOptions{
+			min_idle: Some(1),
+			max_size: 10,
+			idle_timeout: Some(Duration::from_secs(10 * 60)),
+			max_lifetime: Some(Duration::from_secs(30 * 60)),
+			test_on_checkout: true,
+			checkout_timeout: Duration::from_secs(30),
+		}
+	}
+}
+
+pub struct ConnectionManager {
+	conn_opts: ConnectOptions,
+	connector: Connector,
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+	type Connection = PostgresClient;
+	type Error = DbError;
+
+	fn connect(&self) -> Result<PostgresClient, DbError> {
+		PostgresClient::connect_with(&self.conn_opts, &self.connector)
+	}
+
+	fn is_valid(&self, conn: &mut PostgresClient) -> Result<(), DbError> {
+		conn.batch_execute("SELECT 1")
+	}
+
+	fn has_broken(&self, conn: &mut PostgresClient) -> bool {
+		is_broken(conn.is_closed(), conn.transaction_depth)
+	}
+}
+
+/// A connection returned in the middle of a transaction can't be reused,
+/// the next user would run inside it.
+fn is_broken(closed: bool, transaction_depth: u32) -> bool {
+	closed || transaction_depth > 0
+}
+
+fn builder<M: r2d2::ManageConnection>(opts: &PoolOptions) -> r2d2::Builder<M> {
+	r2d2::Pool::builder()
+		.min_idle(opts.min_idle)
+		.max_size(opts.max_size)
+		.idle_timeout(opts.idle_timeout)
+		.max_lifetime(opts.max_lifetime)
+		.test_on_check_out(opts.test_on_checkout)
+		.connection_timeout(opts.checkout_timeout)
+}
+
+#[derive(Clone)]
+pub struct PostgresPool {
+	pool: r2d2::Pool<ConnectionManager>,
+}
+
+impl PostgresPool {
+	/// Creates the pool, opening `min_idle` connections up front.
+	pub fn connect_with_str(conn_string: &str, opts: &PoolOptions) -> Result<PostgresPool, DbError> {
+		PostgresPool::connect_with_opts(&ConnectOptions::from_url(conn_string)?, opts)
+	}
+
+	pub fn connect_with_opts(conn_opts: &ConnectOptions, opts: &PoolOptions) -> Result<PostgresPool, DbError> {
+		let manager = ConnectionManager{
+			conn_opts: conn_opts.clone(),
+			connector: Connector::for_opts(conn_opts)?,
+		};
+		let pool = builder(opts).build(manager)?;
+		Ok(PostgresPool{
+			pool,
+		})
+	}
+
+	/// Checks out a connection, waiting up to `checkout_timeout`.
+	pub fn get(&self) -> Result<PooledClient, DbError> {
+		Ok(PooledClient(self.pool.get()?))
+	}
+
+	pub fn try_get(&self) -> Option<PooledClient> {
+		self.pool.try_get().map(PooledClient)
+	}
+
+	/// Number of open connections, idle or checked out.
+	pub fn connections(&self) -> u32 {
+		self.pool.state().connections
+	}
+
+	pub fn idle_connections(&self) -> u32 {
+		self.pool.state().idle_connections
+	}
+}
+
+/// A checked out connection. Derefs to `PostgresClient` and goes back to the
+/// pool when dropped.
+pub struct PooledClient(r2d2::PooledConnection<ConnectionManager>);
+
+impl Deref for PooledClient {
+	type Target = PostgresClient;
+
+	fn deref(&self) -> &PostgresClient {
+		&self.0
+	}
+}
+
+impl DerefMut for PooledClient {
+	fn deref_mut(&mut self) -> &mut PostgresClient {
+		&mut self.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Hands out connections that are always healthy.
+	struct Manager;
+
+	impl r2d2::ManageConnection for Manager {
+		type Connection = ();
+		type Error = DbError;
+
+		fn connect(&self) -> Result<(), DbError> {
+			Ok(())
+		}
+
+		fn is_valid(&self, _: &mut ()) -> Result<(), DbError> {
+			Ok(())
+		}
+
+		fn has_broken(&self, _: &mut ()) -> bool {
+			false
+		}
+	}
+
+	#[test]
+	fn maps_options_to_the_builder() {
+		let opts = PoolOptions{
+			min_idle: Some(2),
+			max_size: 5,
+			idle_timeout: Some(Duration::from_secs(60)),
+			max_lifetime: None,
+			test_on_checkout: false,
+			checkout_timeout: Duration::from_secs(3),
+		};
+		let pool = builder(&opts).build(Manager).unwrap();
+		assert_eq!(pool.min_idle(), Some(2));
+		assert_eq!(pool.max_size(), 5);
+		assert_eq!(pool.idle_timeout(), Some(Duration::from_secs(60)));
+		assert_eq!(pool.max_lifetime(), None);
+		assert!(!pool.test_on_check_out());
+		assert_eq!(pool.connection_timeout(), Duration::from_secs(3));
+		assert_eq!(pool.state().connections, 2);
+	}
+
+	#[test]
+	fn opens_one_connection_by_default() {
+		let opts = PoolOptions::default();
+		let pool = builder(&opts).build(Manager).unwrap();
+		assert_eq!(pool.min_idle(), Some(1));
+		assert_eq!(pool.max_size(), 10);
+		assert!(pool.test_on_check_out());
+		assert_eq!(pool.state().connections, 1);
+	}
+
+	#[test]
+	fn connections_inside_transactions_are_broken() {
+		assert!(!is_broken(false, 0));
+		assert!(is_broken(false, 1));
+		assert!(is_broken(true, 0));
+	}
+}
@@ -0,0 +1,319 @@
+//! Typed SELECT builder with parameter-bound WHERE predicates.
+use std::marker::PhantomData;
+
+use postgres::types::ToSql;
+
+use rs_pg_scheme::{Scheme, quote_ident};
+use crate::cursor::SelectIter;
+use crate::database::PostgresClient;
+use crate::entity::{Entity, FromRow};
+use crate::error::{DbError, DataError};
+use crate::sql::{check_column, load_scheme};
+
+/// A value bound to a query parameter.
+pub type Value = Box<dyn ToSql + Sync + Send>;
+
+pub enum Filter {
+	Eq(String, Value),
+	Ne(String, Value),
+	Lt(String, Value),
+	Le(String, Value),
+	Gt(String, Value),
+	Ge(String, Value),
+	In(String, Vec<Value>),
+	Like(String, String),
+	IsNull(String),
+	And(Vec<Filter>),
+	Or(Vec<Filter>),
+	Not(Box<Filter>),
+}
+
+impl Filter {
+	pub fn eq<V: ToSql + Sync + Send + 'static>(column: &str, v: V) -> Filter {
+		Filter::Eq(column.to_string(), Box::new(v))
+	}
+
+	pub fn ne<V: ToSql + Sync + Send + 'static>(column: &str, v: V) -> Filter {
+		Filter::Ne(column.to_string(), Box::new(v))
+	}
+
+	pub fn lt<V: ToSql + Sync + Send + 'static>(column: &str, v: V) -> Filter {
+		Filter::Lt(column.to_string(), Box::new(v))
+	}
+
+	pub fn le<V: ToSql + Sync + Send + 'static>(column: &str, v: V) -> Filter {
+		Filter::Le(column.to_string(), Box::new(v))
+	}
+
+	pub fn gt<V: ToSql + Sync + Send + 'static>(column: &str, v: V) -> Filter {
+		Filter::Gt(column.to_string(), Box::new(v))
+	}
+
+	pub fn ge<V: ToSql + Sync + Send + 'static>(column: &str, v: V) -> Filter {
+		Filter::Ge(column.to_string(), Box::new(v))
+	}
+
+	pub fn is_in<V, I>(column: &str, values: I) -> Filter
+	where V: ToSql + Sync + Send + 'static,
+	      I: IntoIterator<Item = V> {
+		Filter::In(column.to_string(), values.into_iter().map(|v| Box::new(v) as Value).collect())
+	}
+
+	pub fn like(column: &str, pattern: &str) -> Filter {
+		Filter::Like(column.to_string(), pattern.to_string())
+	}
+
+	pub fn is_null(column: &str) -> Filter {
+		Filter::IsNull(column.to_string())
+	}
+
+	pub fn and(self, other: Filter) -> Filter {
+		match self {
+			Filter::And(mut v) => {
+				v.push(other);
+				Filter::And(v)
+			}
+			f => Filter::And(vec!(f, other)),
+		}
+	}
+
+	pub fn or(self, other: Filter) -> Filter {
+		match self {
+			Filter::Or(mut v) => {
+				v.push(other);
+				Filter::Or(v)
+			}
+			f => Filter::Or(vec!(f, other)),
+		}
+	}
+
+	/// Appends the predicate to `query`, pushing every bound value to `params`.
+	/// Placeholders are numbered after the parameters already collected.
+	pub fn write_sql<'a>(&'a self, scheme: &Scheme, query: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) -> Result<(), DbError> {
+		match self {
+			Filter::Eq(c, v) => write_cmp(scheme, c, "=", v, query, params)?,
+			Filter::Ne(c, v) => write_cmp(scheme, c, "<>", v, query, params)?,
+			Filter::Lt(c, v) => write_cmp(scheme, c, "<", v, query, params)?,
+			Filter::Le(c, v) => write_cmp(scheme, c, "<=", v, query, params)?,
+			Filter::Gt(c, v) => write_cmp(scheme, c, ">", v, query, params)?,
+			Filter::Ge(c, v) => write_cmp(scheme, c, ">=", v, query, params)?,
+			Filter::In(c, values) => {
+				check_column(scheme, c)?;
+				if values.is_empty() {
+					query.push_str("FALSE");
+					return Ok(());
+				}
+				query.push_str(&quote_ident(c));
+				query.push_str(" IN (");
+				for v in values {
+					params.push(v.as_ref());
+					query.push_str(&format!("${}, ", params.len()));
+				}
+				query.pop();
+				query.pop();
+				query.push(')');
+			}
+			Filter::Like(c, pattern) => {
+				check_column(scheme, c)?;
+				params.push(pattern);
+				query.push_str(&format!("{} LIKE ${}", quote_ident(c), params.len()));
+			}
+			Filter::IsNull(c) => {
+				check_column(scheme, c)?;
+				query.push_str(&format!("{} IS NULL", quote_ident(c)));
+			}
+			Filter::And(filters) => write_group(scheme, filters, " AND ", "TRUE", query, params)?,
+			Filter::Or(filters) => write_group(scheme, filters, " OR ", "FALSE", query, params)?,
+			Filter::Not(f) => {
+				query.push_str("NOT (");
+				f.write_sql(scheme, query, params)?;
+				query.push(')');
+			}
+		}
+		Ok(())
+	}
+}
+
+impl std::ops::Not for Filter {
+	type Output = Filter;
+
+	fn not(self) -> Filter {
+		Filter::Not(Box::new(self))
+	}
+}
+
+fn write_cmp<'a>(scheme: &Scheme, column: &str, op: &str, v: &'a Value, query: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) -> Result<(), DbError> {
+	check_column(scheme, column)?;
+	params.push(v.as_ref());
+	query.push_str(&format!("{} {} ${}", quote_ident(column), op, params.len()));
+	Ok(())
+}
+
+fn write_group<'a>(scheme: &Scheme, filters: &'a [Filter], sep: &str, empty: &str, query: &mut String, params: &mut Vec<&'a (dyn ToSql + Sync)>) -> Result<(), DbError> {
+	if filters.is_empty() {
+		query.push_str(empty);
+		return Ok(());
+	}
+	query.push('(');
+	for (i, f) in filters.iter().enumerate() {
+		if i > 0 {
+			query.push_str(sep);
+		}
+		f.write_sql(scheme, query, params)?;
+	}
+	query.push(')');
+	Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Order {
+	Asc,
+	Desc
+}
+
+impl std::fmt::Display for Order {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Order::Asc => write!(f, "ASC"),
+			Order::Desc => write!(f, "DESC")
+		}
+	}
+}
+
+/// SELECT over the table of `T`.
+pub struct Query<T> {
+	filter: Option<Filter>,
+	order: Vec<(String, Order)>,
+	limit: Option<i64>,
+	offset: Option<i64>,
+	_entity: PhantomData<fn() -> T>,
+}
+
+impl<T: Entity> Default for Query<T> {
+	fn default() -> Self {
+		Query::new()
+	}
+}
+
+impl<T: Entity> Query<T> {
+	pub fn new() -> Query<T> {
+		Query{
+			filter: None,
+			order: vec!(),
+			limit: None,
+			offset: None,
+			_entity: PhantomData,
+		}
+	}
+
+	/// Adds a predicate; repeated calls are combined with AND.
+	pub fn filter(mut self, f: Filter) -> Self {
+		self.filter = Some(match self.filter.take() {
+			Some(prev) => prev.and(f),
+			None => f,
+		});
+		self
+	}
+
+	pub fn order_by(mut self, column: &str, order: Order) -> Self {
+		self.order.push((column.to_string(), order));
+		self
+	}
+
+	pub fn limit(mut self, n: i64) -> Self {
+		self.limit = Some(n);
+		self
+	}
+
+	pub fn offset(mut self, n: i64) -> Self {
+		self.offset = Some(n);
+		self
+	}
+
+	pub fn to_sql(&self) -> Result<(String, Vec<&(dyn ToSql + Sync)>), DbError> {
+		let scheme = load_scheme::<T>()?;
+		let mut query = format!("SELECT * FROM {}", quote_ident(&scheme.name));
+		let mut params: Vec<&(dyn ToSql + Sync)> = vec!();
+
+		if let Some(f) = &self.filter {
+			query += " WHERE ";
+			f.write_sql(scheme, &mut query, &mut params)?;
+		}
+
+		if !self.order.is_empty() {
+			query += " ORDER BY ";
+			for (column, order) in self.order.iter() {
+				check_column(scheme, column)?;
+				query += format!("{} {}, ", quote_ident(column), order).as_str();
+			}
+			query.pop();
+			query.pop();
+		}
+
+		if let Some(limit) = &self.limit {
+			params.push(limit);
+			query += format!(" LIMIT ${}", params.len()).as_str();
+		}
+		if let Some(offset) = &self.offset {
+			params.push(offset);
+			query += format!(" OFFSET ${}", params.len()).as_str();
+		}
+		Ok((query, params))
+	}
+}
+
+/// A [`Query`] bound to a client, returned by `PostgresClient::select`.
+pub struct Select<'c, T> {
+	client: &'c mut PostgresClient,
+	query: Query<T>,
+}
+
+impl<'c, T> Select<'c, T>
+where T: Entity + FromRow {
+	pub(crate) fn new(client: &'c mut PostgresClient) -> Self {
+		Select{
+			client,
+			query: Query::new(),
+		}
+	}
+
+	pub fn filter(mut self, f: Filter) -> Self {
+		self.query = self.query.filter(f);
+		self
+	}
+
+	pub fn order_by(mut self, column: &str, order: Order) -> Self {
+		self.query = self.query.order_by(column, order);
+		self
+	}
+
+	pub fn limit(mut self, n: i64) -> Self {
+		self.query = self.query.limit(n);
+		self
+	}
+
+	pub fn offset(mut self, n: i64) -> Self {
+		self.query = self.query.offset(n);
+		self
+	}
+
+	pub fn fetch(self) -> Result<Vec<T>, DbError> {
+		self.client.fetch(&self.query)
+	}
+
+	pub fn fetch_one(self) -> Result<T, DbError> {
+		let mut rows = self.client.fetch(&self.query)?;
+		if rows.is_empty() {
+			Err(DbError::DataError(DataError::ZeroRecordReturned))
+		} else if rows.len() > 1 {
+			Err(DbError::DataError(DataError::MoreThan1RecordReturned))
+		} else {
+			Ok(rows.remove(0))
+		}
+	}
+
+	/// Streams the rows through a cursor, see `PostgresClient::select_iter`.
+	pub fn iter(self, fetch_size: Option<u32>) -> Result<SelectIter<'c, T>, DbError> {
+		self.client.select_iter(&self.query, fetch_size)
+	}
+}
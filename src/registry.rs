@@ -0,0 +1,113 @@
+//! Creating and dropping the tables of many entities in dependency order.
+use rs_pg_scheme::{Constraint, Scheme, quote_ident};
+use crate::database::{CreateTableOptions, PostgresClient};
+use crate::entity::Entity;
+use crate::error::DbError;
+use crate::sql;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DropMode {
+	/// Also drop views and foreign keys of other tables depending on the table.
+	Cascade,
+	/// Fail if anything outside the registry depends on the table.
+	Restrict
+}
+
+impl std::fmt::Display for DropMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			DropMode::Cascade => write!(f, "CASCADE"),
+			DropMode::Restrict => write!(f, "RESTRICT")
+		}
+	}
+}
+
+/// Schemes of a set of entities, created so that every referenced table
+/// exists before the tables referencing it.
+#[derive(Default)]
+pub struct SchemaRegistry {
+	schemes: Vec<&'static Scheme>,
+}
+
+impl SchemaRegistry {
+	pub fn new() -> SchemaRegistry {
+		SchemaRegistry::default()
+	}
+
+	pub fn register<T: Entity>(mut self) -> Self {
+		self.schemes.push(T::scheme());
+		self
+	}
+
+	/// Schemes with referenced tables first. References to tables outside
+	/// the registry and to the table itself are ignored.
+	pub fn sorted(&self) -> Result<Vec<&Scheme>, DbError> {
+		for scheme in self.schemes.iter() {
+			sql::check_scheme(scheme)?;
+		}
+		let deps: Vec<Vec<usize>> = self.schemes.iter()
+			.map(|s| self.references(s))
+			.collect();
+
+		let mut res = vec!();
+		let mut done = vec!(false; self.schemes.len());
+		while res.len() < self.schemes.len() {
+			let next = (0..self.schemes.len())
+				.find(|&i| !done[i] && deps[i].iter().all(|&d| done[d]));
+			match next {
+				Some(i) => {
+					done[i] = true;
+					res.push(self.schemes[i]);
+				}
+				None => {
+					let cycle = (0..self.schemes.len())
+						.filter(|&i| !done[i])
+						.map(|i| self.schemes[i].name.clone())
+						.collect();
+					return Err(DbError::ReferenceCycle(cycle));
+				}
+			}
+		}
+		Ok(res)
+	}
+
+	fn references(&self, scheme: &Scheme) -> Vec<usize> {
+		let mut res = vec!();
+		for field in scheme.fields.iter() {
+			for constr in field.constraints.iter() {
+				if let Constraint::References(table, _, _, _) = constr {
+					if *table == scheme.name {
+						continue;
+					}
+					if let Some(i) = self.schemes.iter().position(|s| s.name == *table) {
+						res.push(i);
+					}
+				}
+			}
+		}
+		res
+	}
+}
+
+impl PostgresClient {
+	/// Creates the tables of every registered entity in one transaction.
+	pub fn create_all(&mut self, registry: &SchemaRegistry, opts: CreateTableOptions) -> Result<(), DbError> {
+		let schemes = registry.sorted()?;
+		let mut tx = self.begin()?;
+		for scheme in schemes {
+			tx.batch_execute(sql::create_table(scheme, &opts).as_str())?;
+		}
+		tx.commit()
+	}
+
+	/// Drops the tables of every registered entity in one transaction,
+	/// referencing tables first.
+	pub fn drop_all(&mut self, registry: &SchemaRegistry, mode: DropMode) -> Result<(), DbError> {
+		let schemes = registry.sorted()?;
+		let mut tx = self.begin()?;
+		for scheme in schemes.iter().rev() {
+			tx.batch_execute(format!("DROP TABLE IF EXISTS {} {}", quote_ident(&scheme.name), mode).as_str())?;
+		}
+		tx.commit()
+	}
+}
@@ -0,0 +1,138 @@
+//! Re-running transactions aborted by serialization failures or deadlocks.
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::database::PostgresClient;
+use crate::error::DbError;
+use crate::transaction::{Transaction, TransactionOptions, IsolationLevel};
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+	/// Total number of attempts, including the first one.
+	pub max_attempts: u32,
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+	pub multiplier: f64,
+	/// Sleep a random duration between zero and the computed backoff.
+	pub jitter: bool,
+	pub transaction: TransactionOptions,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		RetryPolicy{
+			max_attempts: 5,
+			initial_backoff: Duration::from_millis(10),
+			max_backoff: Duration::from_secs(1),
+			multiplier: 2.0,
+			jitter: true,
+			transaction: TransactionOptions{
+				isolation_level: Some(IsolationLevel::Serializable),
+				..Default::default()
+			},
+		}
+	}
+}
+
+impl RetryPolicy {
+	fn validate(&self) -> Result<(), DbError> {
+		if !self.multiplier.is_finite() || self.multiplier < 0.0 {
+			return Err(DbError::InvalidConfig(format!("retry multiplier must be finite and not negative, got {}", self.multiplier)));
+		}
+		Ok(())
+	}
+
+	/// Computed in seconds, as the factor overflows `Duration` after enough
+	/// attempts.
+	fn backoff(&self, attempt: u32) -> Duration {
+		let secs = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+		let backoff = if secs.is_nan() {
+			// zero initial backoff times an infinite factor
+			Duration::ZERO
+		} else if secs >= self.max_backoff.as_secs_f64() {
+			self.max_backoff
+		} else {
+			Duration::from_secs_f64(secs.max(0.0))
+		};
+		if self.jitter {
+			backoff.mul_f64(random_fraction())
+		} else {
+			backoff
+		}
+	}
+}
+
+pub struct RetryReport<R> {
+	pub value: R,
+	pub attempts: u32,
+}
+
+fn random_fraction() -> f64 {
+	let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+	let mut x = nanos as u64 ^ 0x9E37_79B9_7F4A_7C15;
+	x ^= x << 13;
+	x ^= x >> 7;
+	x ^= x << 17;
+	(x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl PostgresClient {
+	/// Runs `f` in a transaction, starting over when the server reports a
+	/// serialization failure or deadlock, up to `policy.max_attempts` times.
+	pub fn run_retrying<F, R>(&mut self, policy: &RetryPolicy, mut f: F) -> Result<RetryReport<R>, DbError>
+	where F: FnMut(&mut Transaction<'_>) -> Result<R, DbError> {
+		if self.transaction_depth > 0 {
+			return Err(DbError::UnsupportedOperation("retrying inside a transaction".to_string()));
+		}
+		policy.validate()?;
+
+		let mut attempts = 0;
+		loop {
+			attempts += 1;
+			match self.transaction_with(&policy.transaction, &mut f) {
+				Ok(value) => return Ok(RetryReport{value, attempts}),
+				Err(e) if e.is_retryable() => {
+					if attempts >= policy.max_attempts {
+						return Err(DbError::RetriesExhausted{attempts, source: Box::new(e)});
+					}
+					thread::sleep(policy.backoff(attempts));
+				}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn policy(multiplier: f64) -> RetryPolicy {
+		RetryPolicy{
+			multiplier,
+			jitter: false,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn backoff_grows_up_to_max() {
+		let p = policy(2.0);
+		assert_eq!(p.backoff(1), Duration::from_millis(10));
+		assert_eq!(p.backoff(3), Duration::from_millis(40));
+		assert_eq!(p.backoff(8), p.max_backoff);
+		assert_eq!(p.backoff(100), p.max_backoff);
+		assert_eq!(p.backoff(u32::MAX), p.max_backoff);
+	}
+
+	#[test]
+	fn backoff_survives_degenerate_policies() {
+		let zero = RetryPolicy{initial_backoff: Duration::ZERO, ..policy(2.0)};
+		assert_eq!(zero.backoff(2000), Duration::ZERO);
+		assert_eq!(policy(0.0).backoff(5), Duration::ZERO);
+		assert!(policy(-2.0).validate().is_err());
+		assert!(policy(f64::NAN).validate().is_err());
+		assert!(policy(f64::INFINITY).validate().is_err());
+		assert!(policy(0.5).validate().is_ok());
+	}
+}
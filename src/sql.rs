@@ -0,0 +1,989 @@
+//! SQL generation shared by the blocking and async clients.
+use postgres::Row;
+use bytes::BytesMut;
+use std::sync::RwLock;
+use postgres::types::{ToSql, Type, WrongType};
+
+use rs_pg_scheme::{Constraint, Field, PgType, Scheme, pg_type_name, pg_type_to_str, quote_ident};
+use crate::database::{CreateTableOptions, ConflictAction, ConflictTarget, UpsertOptions};
+use crate::entity::{Entity, PrimaryKey, WithId};
+use crate::error::{DbError, DataError};
+
+/// Query text together with the values bound to its placeholders.
+pub(crate) struct SqlQuery<'a> {
+	pub text: String,
+	values: Vec<&'a (dyn ToSql + Sync)>,
+}
+
+impl<'a> SqlQuery<'a> {
+	fn new(text: String) -> SqlQuery<'a> {
+		SqlQuery{
+			text,
+			values: vec!(),
+		}
+	}
+
+	/// Writes the next placeholder and stores its value.
+	fn bind(&mut self, v: &'a (dyn ToSql + Sync)) {
+		self.values.push(v);
+		self.text += format!("${}", self.values.len()).as_str();
+	}
+
+	pub fn params(&self) -> Vec<&'a (dyn ToSql + Sync)> {
+		self.values.clone()
+	}
+}
+
+/// Schemes, by address, that passed a check. Schemes are built once per
+/// entity type, so what held for one call holds for every later one.
+struct SchemeSet(RwLock<Vec<usize>>);
+
+impl SchemeSet {
+	const fn new() -> SchemeSet {
+		SchemeSet(RwLock::new(Vec::new()))
+	}
+
+	fn contains(&self, scheme: &'static Scheme) -> bool {
+		self.0.read().unwrap().contains(&(scheme as *const Scheme as usize))
+	}
+
+	fn insert(&self, scheme: &'static Scheme) {
+		self.0.write().unwrap().push(scheme as *const Scheme as usize);
+	}
+}
+
+/// Entities whose values were found to fit the types of their columns.
+static CHECKED_TYPES: SchemeSet = SchemeSet::new();
+
+/// Entities whose scheme passed `validate_scheme`.
+static VALID_SCHEMES: SchemeSet = SchemeSet::new();
+
+/// Longest identifier postgres stores without truncating it.
+const MAX_IDENT_LEN: usize = 63;
+
+/// `T::scheme()`, checked to be safe to put into SQL.
+pub(crate) fn load_scheme<T: Entity>() -> Result<&'static Scheme, DbError> {
+	let scheme = T::scheme();
+	check_scheme(scheme)?;
+	Ok(scheme)
+}
+
+/// `validate_scheme`, run until it passes once for `scheme`.
+pub(crate) fn check_scheme(scheme: &'static Scheme) -> Result<(), DbError> {
+	if !VALID_SCHEMES.contains(scheme) {
+		validate_scheme(scheme)?;
+		VALID_SCHEMES.insert(scheme);
+	}
+	Ok(())
+}
+
+/// Identifiers are quoted wherever they are used, but names that could
+/// only come from an attempt to break out of the quotes are refused, as
+/// are check and default expressions that don't stay inside their clause.
+pub(crate) fn validate_scheme(scheme: &Scheme) -> Result<(), DbError> {
+	check_ident(&scheme.name)?;
+	for pk in scheme.pk_fields.iter() {
+		check_ident(&pk.name)?;
+	}
+	for field in scheme.fields.iter() {
+		check_ident(&field.name)?;
+		for constr in field.constraints.iter() {
+			check_constraint(constr)?;
+		}
+	}
+	for constr in scheme.constraints.iter() {
+		check_constraint(constr)?;
+	}
+	Ok(())
+}
+
+fn check_constraint(constr: &Constraint) -> Result<(), DbError> {
+	match constr {
+		Constraint::References(table, column, _, _) => {
+			check_ident(table)?;
+			check_ident(column)
+		}
+		Constraint::Check(expr) | Constraint::Default(expr) => check_expr(expr),
+		_ => Ok(()),
+	}
+}
+
+pub(crate) fn check_ident(name: &str) -> Result<(), DbError> {
+	if name.is_empty() || name.len() > MAX_IDENT_LEN
+		|| name.chars().any(|c| c.is_control() || c == '"' || c == ';')
+		|| name.contains("--") || name.contains("/*") {
+		return Err(DbError::SqlInjectionAttempt(name.to_string()));
+	}
+	Ok(())
+}
+
+/// Accepts an expression whose strings and quoted identifiers are closed,
+/// whose parentheses are balanced, and which has no statement separator,
+/// comment or dollar quoting outside of quotes.
+fn check_expr(expr: &str) -> Result<(), DbError> {
+	let err = || Err(DbError::SqlInjectionAttempt(expr.to_string()));
+	if expr.trim().is_empty() {
+		return err();
+	}
+
+	let mut depth = 0;
+	let mut chars = expr.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'\'' | '"' => loop {
+				match chars.next() {
+					// a doubled quote stands for itself
+					Some(q) if q == c && chars.peek() == Some(&c) => { chars.next(); }
+					Some(q) if q == c => break,
+					Some('\0') | None => return err(),
+					Some(_) => {}
+				}
+			},
+			'(' => depth += 1,
+			')' => {
+				if depth == 0 {
+					return err();
+				}
+				depth -= 1;
+			}
+			';' | '$' => return err(),
+			'-' if chars.peek() == Some(&'-') => return err(),
+			'/' if chars.peek() == Some(&'*') => return err(),
+			c if c.is_control() && !c.is_whitespace() => return err(),
+			_ => {}
+		}
+	}
+	if depth != 0 {
+		return err();
+	}
+	Ok(())
+}
+
+/// Quoted `names`, comma separated.
+fn quote_literal(s: &str) -> String {
+	format!("'{}'", s.replace('\'', "''"))
+}
+
+pub(crate) fn quote_all<S: AsRef<str>>(names: &[S]) -> String {
+	names.iter().map(|n| quote_ident(n.as_ref())).collect::<Vec<String>>().join(", ")
+}
+
+pub(crate) fn create_table(scheme: &Scheme, opts: &CreateTableOptions) -> String {
+
+	let mut query = String::from("CREATE ");
+	if opts.temp {
+		query += "TEMP ";
+	}
+	query += "TABLE ";
+	if opts.if_not_exists {
+		query += "IF NOT EXISTS ";
+	}
+	query += quote_ident(&scheme.name).as_str();
+	query += "(";
+
+	// a composite key can't be declared on its columns
+	let composite_pk = scheme.pk_fields.len() > 1;
+	for field in scheme.fields.iter() {
+		query += quote_ident(&field.name).as_str();
+		query += " ";
+		query += pg_type_to_str(&field.ty);
+		query += " ";
+
+		for constr in field.constraints.iter() {
+			if composite_pk && matches!(constr, Constraint::PrimaryKey) {
+				continue;
+			}
+			query += constr.to_string().as_str();
+			query += " ";
+		}
+		query += ","
+	}
+	for constr in scheme.constraints.iter() {
+		query += constr.to_string().as_str();
+		query += ",";
+	}
+	if composite_pk {
+		let names: Vec<&str> = scheme.pk_fields.iter().map(|pk| pk.name.as_str()).collect();
+		query += format!("PRIMARY KEY ({}),", quote_all(&names)).as_str();
+	}
+	query.pop();
+	query += ")";
+	query
+}
+
+/// Multi-row INSERT of `items`, optionally returning the primary key of
+/// every inserted row.
+pub(crate) fn insert<T: Entity>(items: &[T], returning_pk: bool) -> Result<SqlQuery<'_>, DbError> {
+	let mut query = insert_values(items, false)?;
+	if returning_pk {
+		query.text += format!(" RETURNING {}", quote_all(&pk_names(load_scheme::<T>()?)?)).as_str();
+	}
+	Ok(query)
+}
+
+/// `INSERT ... VALUES` of `items`. Columns generated by the database get
+/// `DEFAULT` unless `send_generated` is set; a sent serial value of 0 still
+/// takes the next value of the sequence.
+fn insert_values<T: Entity>(items: &[T], send_generated: bool) -> Result<SqlQuery<'_>, DbError> {
+	if items.is_empty() {
+		return Err(DbError::DataError(DataError::EmptyVector));
+	}
+	let scheme = load_scheme::<T>()?;
+	let names: Vec<&str> = scheme.fields.iter().map(|f| f.name.as_str()).collect();
+	if names.is_empty() {
+		return Err(DbError::SerializationError(format!("{} has no columns", scheme.name)));
+	}
+	let mut query = SqlQuery::new(format!("INSERT INTO {}({}) VALUES ", quote_ident(&scheme.name), quote_all(&names)));
+
+	for item in items {
+		query.text += "(";
+		for (field, val) in entity_values(scheme, item)? {
+			if generated_by_db(field) && !send_generated {
+				query.text += "DEFAULT";
+			} else if field.ty == PgType::Serial {
+				query.text += "COALESCE(NULLIF(";
+				query.bind(val);
+				query.text += format!(", 0), nextval(pg_get_serial_sequence({}, {})))",
+					quote_literal(&quote_ident(&scheme.name)), quote_literal(&field.name)).as_str();
+			} else {
+				query.bind(val);
+			}
+			query.text += ", ";
+		}
+		query.text.pop();
+		query.text.pop();
+		query.text += "),";
+	}
+	query.text.pop();
+	Ok(query)
+}
+
+/// INSERT of `items` with an `ON CONFLICT` clause, optionally returning
+/// the stored rows.
+pub(crate) fn upsert<'a, T: Entity>(items: &'a [T], opts: &UpsertOptions, returning: bool) -> Result<SqlQuery<'a>, DbError> {
+
+	let scheme = load_scheme::<T>()?;
+	// rows can only conflict on generated columns if their values are sent
+	let send_generated = match &opts.target {
+		ConflictTarget::PrimaryKey => true,
+		ConflictTarget::Columns(names) => scheme.fields.iter().any(|f| generated_by_db(f) && names.contains(&f.name)),
+		ConflictTarget::Constraint(_) => false,
+	};
+	// a defaulted key has no value meaning "not set yet"
+	if let Some(field) = scheme.fields.iter().find(|f| send_generated && generated_by_db(f) && f.ty != PgType::Serial) {
+		return Err(DbError::UnsupportedOperation(format!("{} is generated by the database and can't be a conflict target", field.name)));
+	}
+	let mut query = insert_values(items, send_generated)?;
+
+	let target_columns = match &opts.target {
+		ConflictTarget::PrimaryKey => {
+			let names = pk_names(scheme)?;
+			query.text += format!(" ON CONFLICT ({})", quote_all(&names)).as_str();
+			names
+		}
+		ConflictTarget::Columns(names) => {
+			if names.is_empty() {
+				return Err(DbError::UnsupportedOperation("conflict target has no columns".to_string()));
+			}
+			for name in names.iter() {
+				check_column(scheme, name)?;
+			}
+			query.text += format!(" ON CONFLICT ({})", quote_all(names)).as_str();
+			names.clone()
+		}
+		ConflictTarget::Constraint(name) => {
+			check_ident(name)?;
+			query.text += format!(" ON CONFLICT ON CONSTRAINT {}", quote_ident(name)).as_str();
+			scheme.pk_fields.iter().map(|pk| pk.name.clone()).collect()
+		}
+	};
+
+	let update_columns: Vec<String> = match &opts.action {
+		ConflictAction::DoNothing => vec!(),
+		ConflictAction::UpdateAll => scheme.fields.iter()
+			.filter(|f| f.ty != PgType::Serial && !target_columns.contains(&f.name))
+			.map(|f| f.name.clone())
+			.collect(),
+		ConflictAction::Update(names) => {
+			for name in names.iter() {
+				check_column(scheme, name)?;
+			}
+			names.clone()
+		}
+	};
+
+	if update_columns.is_empty() {
+		if opts.action != ConflictAction::DoNothing {
+			return Err(DbError::UnsupportedOperation(format!("no columns of {} to update on conflict", scheme.name)));
+		}
+		query.text += " DO NOTHING";
+	} else {
+		let sets: Vec<String> = update_columns.iter().map(|c| format!("{0} = EXCLUDED.{0}", quote_ident(c))).collect();
+		query.text += format!(" DO UPDATE SET {}", sets.join(", ")).as_str();
+	}
+
+	if returning {
+		query.text += " RETURNING *";
+	}
+	Ok(query)
+}
+
+pub(crate) fn check_column(scheme: &Scheme, column: &str) -> Result<(), DbError> {
+	if scheme.field(column).is_some() {
+		Ok(())
+	} else {
+		Err(DbError::UnknownColumn(column.to_string()))
+	}
+}
+
+/// UPDATE of every non-key column of the row with the key of `item`.
+pub(crate) fn update<K, T>(item: &T) -> Result<SqlQuery<'_>, DbError>
+where K: PrimaryKey,
+      T: Entity + WithId<K> {
+
+	let scheme = load_scheme::<T>()?;
+	let pk_names = pk_names(scheme)?;
+	let mut query = SqlQuery::new(format!("UPDATE {} SET ", quote_ident(&scheme.name)));
+	let check_types = !CHECKED_TYPES.contains(scheme);
+
+	let mut columns = 0;
+	for (field, val) in entity_values(scheme, item)? {
+		if pk_names.contains(&field.name) {
+			continue;
+		}
+		query.text += quote_ident(&field.name).as_str();
+		query.text += " = ";
+		if field.ty == PgType::Serial {
+			query.text += "DEFAULT";
+		} else {
+			query.bind(val);
+		}
+		query.text += ", ";
+		columns += 1;
+	}
+	if columns == 0 {
+		return Err(DbError::UnsupportedOperation(format!("{} has no columns to update", scheme.name)));
+	}
+	query.text.pop();
+	query.text.pop();
+
+	let pk_values = item.__get_pk();
+	if pk_values.len() != scheme.pk_fields.len() {
+		return Err(DbError::SerializationError(format!("{} has {} key columns, {} key values given",
+			scheme.name, scheme.pk_fields.len(), pk_values.len())));
+	}
+	query.text += " WHERE ";
+	query.text += pk_condition(&pk_names, query.values.len()).as_str();
+	let mut buf = BytesMut::new();
+	for (pk, val) in scheme.pk_fields.iter().zip(pk_values) {
+		if check_types {
+			check_type(&pk.name, &pk.ty, val, &mut buf)?;
+		}
+		query.values.push(val);
+	}
+	Ok(query)
+}
+
+/// DELETE of the rows equal to `item` in every column but serial ones.
+/// Nullable columns are compared with `IS NOT DISTINCT FROM`, so `None`
+/// matches NULL.
+pub(crate) fn delete_full_match<T: Entity>(item: &T) -> Result<SqlQuery<'_>, DbError> {
+
+	let scheme = load_scheme::<T>()?;
+	let mut query = SqlQuery::new(format!("DELETE FROM {} WHERE ", quote_ident(&scheme.name)));
+
+	let mut columns = 0;
+	for (field, val) in entity_values(scheme, item)? {
+		if field.ty == PgType::Serial {
+			continue;
+		}
+
+		query.text += quote_ident(&field.name).as_str();
+		if field.constraints.iter().any(|c| matches!(c, Constraint::Null)) {
+			query.text += " IS NOT DISTINCT FROM ";
+		} else {
+			query.text += " = ";
+		}
+		query.bind(val);
+		query.text += " AND ";
+		columns += 1;
+	}
+	// without a condition every row would match
+	if columns == 0 {
+		return Err(DbError::UnsupportedOperation(format!("{} has no columns to match", scheme.name)));
+	}
+	for _ in 0.." AND ".len() {
+		query.text.pop();
+	}
+	Ok(query)
+}
+
+/// Values of `item` with their fields, in scheme order. Fails if a column
+/// has no value, a value has no column, or a value can't be sent as the
+/// type of its column. Types are checked until they pass once for `T`, as
+/// the fields of a type don't change.
+fn entity_values<'a, T: Entity>(scheme: &'static Scheme, item: &'a T) -> Result<Vec<(&'static Field, &'a (dyn ToSql + Sync))>, DbError> {
+	let values = item.__values();
+	for (name, _) in values.iter() {
+		if scheme.field(name).is_none() {
+			return Err(DbError::SerializationError(format!("{} has no column {}", scheme.name, name)));
+		}
+	}
+
+	let check_types = !CHECKED_TYPES.contains(scheme);
+	let mut res = vec!();
+	let mut buf = BytesMut::new();
+	for field in scheme.fields.iter() {
+		let mut found = values.iter().filter(|(name, _)| *name == field.name);
+		let val = match (found.next(), found.next()) {
+			(Some((_, val)), None) => *val,
+			(None, _) => return Err(DbError::SerializationError(format!("no value for column {}", field.name))),
+			(Some(_), Some(_)) => return Err(DbError::SerializationError(format!("more than one value for column {}", field.name))),
+		};
+		if check_types {
+			check_type(&field.name, &field.ty, val, &mut buf)?;
+		}
+		res.push((field, val));
+	}
+	if check_types {
+		CHECKED_TYPES.insert(scheme);
+	}
+	Ok(res)
+}
+
+fn check_type(name: &str, ty: &PgType, val: &(dyn ToSql + Sync), buf: &mut BytesMut) -> Result<(), DbError> {
+	buf.clear();
+	match val.to_sql_checked(&pg_type(ty), buf) {
+		Ok(_) => Ok(()),
+		Err(e) if e.is::<WrongType>() => Err(DbError::FieldTypeMismatch{
+			field: name.to_string(),
+			expected: pg_type_name(ty).to_string(),
+		}),
+		Err(e) => Err(DbError::SerializationError(format!("{}: {}", name, e))),
+	}
+}
+
+/// `COPY ... FROM STDIN (FORMAT binary)` of the columns that are not left
+/// to the database.
+pub(crate) struct CopyIn {
+	pub text: String,
+	pub types: Vec<Type>,
+	scheme: &'static Scheme,
+}
+
+impl CopyIn {
+	/// Values of `item` for the copied columns, in column order.
+	pub fn values<'a, T: Entity>(&self, item: &'a T) -> Result<Vec<&'a (dyn ToSql + Sync)>, DbError> {
+		Ok(entity_values(self.scheme, item)?.into_iter()
+			.filter(|(field, _)| !generated_by_db(field))
+			.map(|(_, val)| val)
+			.collect())
+	}
+}
+
+pub(crate) fn copy_in<T: Entity>() -> Result<CopyIn, DbError> {
+	let scheme = load_scheme::<T>()?;
+	let fields: Vec<&Field> = scheme.fields.iter().filter(|f| !generated_by_db(f)).collect();
+	if fields.is_empty() {
+		return Err(DbError::UnsupportedOperation(format!("{} has no columns to copy", scheme.name)));
+	}
+	let columns: Vec<&str> = fields.iter().map(|f| f.name.as_str()).collect();
+	Ok(CopyIn{
+		text: format!("COPY {} ({}) FROM STDIN (FORMAT binary)", quote_ident(&scheme.name), quote_all(&columns)),
+		types: fields.iter().map(|f| pg_type(&f.ty)).collect(),
+		scheme,
+	})
+}
+
+/// Rows per `COPY` statement, unlimited for `None`.
+pub(crate) fn batch_size(batch_size: Option<usize>) -> Result<usize, DbError> {
+	match batch_size {
+		Some(0) => Err(DbError::UnsupportedOperation("batch size must be positive".to_string())),
+		Some(n) => Ok(n),
+		None => Ok(usize::MAX),
+	}
+}
+
+fn pg_type(ty: &PgType) -> Type {
+	match ty {
+		PgType::Serial | PgType::Integer => Type::INT4,
+		PgType::Real => Type::FLOAT4,
+		PgType::DoublePrecision => Type::FLOAT8,
+		PgType::Text => Type::TEXT,
+		PgType::Char => Type::CHAR,
+		PgType::SmallInt => Type::INT2,
+		PgType::BigInt => Type::INT8,
+		PgType::Boolean => Type::BOOL,
+		PgType::ByteArray => Type::BYTEA,
+		PgType::TimeStamp => Type::TIMESTAMP,
+		PgType::IpAddr => Type::INET,
+		PgType::Date => Type::DATE,
+		PgType::Time => Type::TIME,
+		PgType::Uuid => Type::UUID,
+	}
+}
+
+/// Serial columns and primary key columns with a default are left to the
+/// database on insert.
+fn generated_by_db(field: &Field) -> bool {
+	field.ty == PgType::Serial
+		|| (field.constraints.iter().any(|c| matches!(c, Constraint::PrimaryKey))
+			&& field.constraints.iter().any(|c| matches!(c, Constraint::Default(_))))
+}
+
+pub(crate) fn select_all<T: Entity>() -> Result<String, DbError> {
+	Ok(format!("SELECT * FROM {}", quote_ident(&load_scheme::<T>()?.name)))
+}
+
+pub(crate) fn select_by_pk<T: Entity>() -> Result<String, DbError> {
+	let scheme = load_scheme::<T>()?;
+	Ok(format!("SELECT * FROM {} WHERE {}", quote_ident(&scheme.name), pk_condition(&pk_names(scheme)?, 0)))
+}
+
+pub(crate) fn delete_by_pk<T: Entity>() -> Result<String, DbError> {
+	let scheme = load_scheme::<T>()?;
+	Ok(format!("DELETE FROM {} WHERE {}", quote_ident(&scheme.name), pk_condition(&pk_names(scheme)?, 0)))
+}
+
+pub(crate) fn count<T: Entity>() -> Result<String, DbError> {
+	Ok(format!("SELECT COUNT(*) FROM {}", quote_ident(&load_scheme::<T>()?.name)))
+}
+
+fn pk_names(scheme: &Scheme) -> Result<Vec<String>, DbError> {
+	if scheme.pk_fields.is_empty() {
+		return Err(DbError::UnsupportedOperation(format!("{} has no primary key", scheme.name)));
+	}
+	Ok(scheme.pk_fields.iter().map(|pk| pk.name.clone()).collect())
+}
+
+/// `a = $n AND b = $n+1 ...`, numbering placeholders after `bound` ones.
+fn pk_condition(pk_names: &[String], bound: usize) -> String {
+	let mut res = String::new();
+	for (i, name) in pk_names.iter().enumerate() {
+		if i > 0 {
+			res += " AND ";
+		}
+		res += format!("{} = ${}", quote_ident(name), bound + i + 1).as_str();
+	}
+	res
+}
+
+/// Checks that exactly one row came back.
+pub(crate) fn single_row(mut rows: Vec<Row>) -> Result<Row, DbError> {
+	if rows.is_empty() {
+		Err(DbError::DataError(DataError::ZeroRecordReturned))
+	} else if rows.len() > 1 {
+		Err(DbError::DataError(DataError::MoreThan1RecordReturned))
+	} else {
+		Ok(rows.remove(0))
+	}
+}
+
+/// Assigns keys returned by `INSERT ... RETURNING` back to `items`.
+pub(crate) fn assign_pks<K, T>(items: &mut [T], rows: &[Row]) -> Result<(), DbError>
+where K: PrimaryKey,
+      T: WithId<K> {
+	if rows.is_empty() {
+		Err(DbError::DataError(DataError::ZeroRecordReturned))
+	} else if rows.len() != items.len() {
+		Err(DbError::DataError(DataError::WrongNumberOfRecordsReturned(items.len(), rows.len())))
+	} else {
+		for (item, row) in items.iter_mut().zip(rows) {
+			item.__set_pk(K::from_row(row)?);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::OnceLock;
+	use rs_pg_scheme::PkField;
+
+	fn people() -> Scheme {
+		let field = |name: &str, ty: PgType, constraints: Vec<Constraint>| Field{name: name.to_string(), ty, constraints};
+		Scheme{
+			name: "people".to_string(),
+			pk_fields: vec!(PkField{name: "id".to_string(), ty: PgType::Serial}),
+			fields: vec!(
+				field("id", PgType::Serial, vec!(Constraint::NotNull, Constraint::PrimaryKey)),
+				field("name", PgType::Text, vec!(Constraint::NotNull)),
+				field("age", PgType::SmallInt, vec!(Constraint::Null)),
+			),
+			constraints: vec!(),
+		}
+	}
+
+	/// An entity of the `people` scheme with hand-written, possibly wrong,
+	/// column values.
+	macro_rules! person_entity {
+		($name:ident { $($field:ident: $ty:ty),* }, |$s:ident| $values:expr) => {
+			struct $name {
+				$($field: $ty),*
+			}
+
+			impl Entity for $name {
+				fn scheme() -> &'static Scheme {
+					static SCHEME: OnceLock<Scheme> = OnceLock::new();
+					SCHEME.get_or_init(people)
+				}
+
+				fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))> {
+					let $s = self;
+					$values
+				}
+			}
+
+			impl WithId<i32> for $name {
+				fn __get_pk(&self) -> Vec<&(dyn ToSql + Sync)> {
+					let $s = self;
+					$s.__values().into_iter().filter(|(n, _)| *n == "id").map(|(_, v)| v).collect()
+				}
+				fn __set_pk(&mut self, _: i32) {}
+				fn __borrow_pk(&self) -> i32 {
+					0
+				}
+			}
+		};
+	}
+
+	person_entity!(Good { id: i32, name: String, age: Option<i16> },
+		|s| vec!(("id", &s.id), ("name", &s.name), ("age", &s.age)));
+	person_entity!(WrongType { id: i32, name: String, age: String },
+		|s| vec!(("id", &s.id), ("name", &s.name), ("age", &s.age)));
+	person_entity!(WrongKeyType { id: String, name: String, age: i16 },
+		|s| vec!(("id", &s.id), ("name", &s.name), ("age", &s.age)));
+	person_entity!(Missing { id: i32, name: String },
+		|s| vec!(("id", &s.id), ("name", &s.name)));
+	person_entity!(Unknown { id: i32, name: String, age: i16, nick: String },
+		|s| vec!(("id", &s.id), ("name", &s.name), ("age", &s.age), ("nick", &s.nick)));
+	person_entity!(Duplicate { id: i32, name: String, age: i16 },
+		|s| vec!(("id", &s.id), ("name", &s.name), ("age", &s.age), ("age", &s.age)));
+
+	struct OnlySerial {
+		id: i32,
+	}
+
+	impl Entity for OnlySerial {
+		fn scheme() -> &'static Scheme {
+			static SCHEME: OnceLock<Scheme> = OnceLock::new();
+			SCHEME.get_or_init(|| {
+				let mut scheme = people();
+				scheme.fields.truncate(1);
+				scheme
+			})
+		}
+
+		fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))> {
+			vec!(("id", &self.id))
+		}
+	}
+
+	struct DefaultedKey {
+		id: String,
+	}
+
+	impl Entity for DefaultedKey {
+		fn scheme() -> &'static Scheme {
+			static SCHEME: OnceLock<Scheme> = OnceLock::new();
+			SCHEME.get_or_init(|| {
+				let mut scheme = people();
+				scheme.fields.truncate(1);
+				scheme.fields[0].ty = PgType::Text;
+				scheme.fields[0].constraints.push(Constraint::Default("md5(random()::text)".to_string()));
+				scheme.pk_fields[0].ty = PgType::Text;
+				scheme
+			})
+		}
+
+		fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))> {
+			vec!(("id", &self.id))
+		}
+	}
+
+	struct Blob {
+		hash: Vec<u8>,
+		data: Vec<u8>,
+	}
+
+	impl Entity for Blob {
+		fn scheme() -> &'static Scheme {
+			static SCHEME: OnceLock<Scheme> = OnceLock::new();
+			SCHEME.get_or_init(|| Scheme{
+				name: "blobs".to_string(),
+				pk_fields: vec!(PkField{name: "hash".to_string(), ty: PgType::ByteArray}),
+				fields: vec!(
+					Field{name: "hash".to_string(), ty: PgType::ByteArray, constraints: vec!(Constraint::NotNull, Constraint::PrimaryKey)},
+					Field{name: "data".to_string(), ty: PgType::ByteArray, constraints: vec!(Constraint::NotNull)},
+				),
+				constraints: vec!(),
+			})
+		}
+
+		fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))> {
+			vec!(("hash", &self.hash), ("data", &self.data))
+		}
+	}
+
+	impl WithId<Vec<u8>> for Blob {
+		fn __get_pk(&self) -> Vec<&(dyn ToSql + Sync)> {
+			vec!(&self.hash)
+		}
+		fn __set_pk(&mut self, v: Vec<u8>) {
+			self.hash = v;
+		}
+		fn __borrow_pk(&self) -> Vec<u8> {
+			self.hash.clone()
+		}
+	}
+
+	fn good() -> Good {
+		Good{id: 1, name: "Ann".to_string(), age: None}
+	}
+
+	fn wrong_type() -> WrongType {
+		WrongType{id: 1, name: "Ann".to_string(), age: "ten".to_string()}
+	}
+
+	fn assert_type_mismatch<T>(res: Result<T, DbError>, column: &str, ty: &str) {
+		match res {
+			Err(DbError::FieldTypeMismatch{field, expected}) => {
+				assert_eq!(field, column);
+				assert_eq!(expected, ty);
+			}
+			Err(e) => panic!("unexpected error {}", e),
+			Ok(_) => panic!("malformed entity accepted"),
+		}
+	}
+
+	fn assert_serialization_error<T>(res: Result<T, DbError>) {
+		match res {
+			Err(DbError::SerializationError(_)) => {}
+			Err(e) => panic!("unexpected error {}", e),
+			Ok(_) => panic!("malformed entity accepted"),
+		}
+	}
+
+	#[test]
+	fn writes_well_formed_entity() {
+		let items = [good()];
+		let query = insert(&items, true).unwrap();
+		assert_eq!(query.text, r#"INSERT INTO "people"("id", "name", "age") VALUES (DEFAULT, $1, $2) RETURNING "id""#);
+		assert_eq!(query.params().len(), 2);
+
+		let item = good();
+		let query = update::<i32, _>(&item).unwrap();
+		assert_eq!(query.text, r#"UPDATE "people" SET "name" = $1, "age" = $2 WHERE "id" = $3"#);
+		assert_eq!(query.params().len(), 3);
+
+		let query = delete_full_match(&item).unwrap();
+		assert_eq!(query.text, r#"DELETE FROM "people" WHERE "name" = $1 AND "age" IS NOT DISTINCT FROM $2"#);
+
+		let copy = copy_in::<Good>().unwrap();
+		assert_eq!(copy.text, r#"COPY "people" ("name", "age") FROM STDIN (FORMAT binary)"#);
+		assert_eq!(copy.values(&item).unwrap().len(), 2);
+	}
+
+	#[test]
+	fn upsert_sends_generated_keys_of_conflict_target() {
+		let items = [good()];
+		let query = upsert(&items, &UpsertOptions::default(), false).unwrap();
+		assert_eq!(query.text, "INSERT INTO \"people\"(\"id\", \"name\", \"age\") \
+			VALUES (COALESCE(NULLIF($1, 0), nextval(pg_get_serial_sequence('\"people\"', 'id'))), $2, $3) \
+			ON CONFLICT (\"id\") DO UPDATE SET \"name\" = EXCLUDED.\"name\", \"age\" = EXCLUDED.\"age\"");
+		assert_eq!(query.params().len(), 3);
+
+		let opts = UpsertOptions{
+			target: ConflictTarget::Columns(vec!("name".to_string())),
+			action: ConflictAction::DoNothing,
+		};
+		let query = upsert(&items, &opts, false).unwrap();
+		assert_eq!(query.text, r#"INSERT INTO "people"("id", "name", "age") VALUES (DEFAULT, $1, $2) ON CONFLICT ("name") DO NOTHING"#);
+		assert_eq!(query.params().len(), 2);
+	}
+
+	#[test]
+	fn upsert_of_new_rows_takes_serial_keys_from_the_sequence() {
+		let items = [good(), Good{id: 0, name: "Bob".to_string(), age: None}];
+		let query = upsert(&items, &UpsertOptions::default(), false).unwrap();
+		assert!(query.text.starts_with("INSERT INTO \"people\"(\"id\", \"name\", \"age\") \
+			VALUES (COALESCE(NULLIF($1, 0), nextval(pg_get_serial_sequence('\"people\"', 'id'))), $2, $3),\
+			(COALESCE(NULLIF($4, 0), nextval(pg_get_serial_sequence('\"people\"', 'id'))), $5, $6) ON CONFLICT"));
+		assert_eq!(query.params().len(), 6);
+	}
+
+	#[test]
+	fn upsert_refuses_defaulted_key_targets() {
+		match upsert(&[DefaultedKey{id: String::new()}], &UpsertOptions::default(), false) {
+			Err(DbError::UnsupportedOperation(_)) => {}
+			Err(e) => panic!("unexpected error {}", e),
+			Ok(q) => panic!("would run {}", q.text),
+		}
+	}
+
+	#[test]
+	fn bytea_keys() {
+		let blob = Blob{hash: vec!(1, 2), data: vec!(3)};
+		let query = insert(std::slice::from_ref(&blob), true).unwrap();
+		assert_eq!(query.text, r#"INSERT INTO "blobs"("hash", "data") VALUES ($1, $2) RETURNING "hash""#);
+
+		let query = update::<Vec<u8>, _>(&blob).unwrap();
+		assert_eq!(query.text, r#"UPDATE "blobs" SET "data" = $1 WHERE "hash" = $2"#);
+		assert_eq!(query.params().len(), 2);
+
+		assert_eq!(select_by_pk::<Blob>().unwrap(), r#"SELECT * FROM "blobs" WHERE "hash" = $1"#);
+	}
+
+	#[test]
+	fn insert_rejects_malformed_entities() {
+		assert_type_mismatch(insert(&[wrong_type()], false), "age", "int2");
+		assert_serialization_error(insert(&[Missing{id: 1, name: "Ann".to_string()}], false));
+		assert_serialization_error(insert(&[Unknown{id: 1, name: "Ann".to_string(), age: 1, nick: "A".to_string()}], false));
+		assert_serialization_error(insert(&[Duplicate{id: 1, name: "Ann".to_string(), age: 1}], false));
+	}
+
+	#[test]
+	fn types_are_checked_until_they_pass() {
+		insert(&[good()], false).unwrap();
+		assert!(CHECKED_TYPES.contains(Good::scheme()));
+		assert!(insert(&[wrong_type()], false).is_err());
+		assert!(!CHECKED_TYPES.contains(WrongType::scheme()));
+		assert!(insert(&[wrong_type()], false).is_err());
+	}
+
+	#[test]
+	fn schemes_are_validated_once() {
+		load_scheme::<Good>().unwrap();
+		assert!(VALID_SCHEMES.contains(Good::scheme()));
+	}
+
+	#[test]
+	fn insert_many_rejects_one_malformed_entity() {
+		let items = [wrong_type(), WrongType{id: 2, name: "Bob".to_string(), age: "".to_string()}];
+		assert_type_mismatch(insert(&items, true), "age", "int2");
+	}
+
+	#[test]
+	fn upsert_rejects_malformed_entities() {
+		let opts = UpsertOptions::default();
+		assert_type_mismatch(upsert(&[wrong_type()], &opts, false), "age", "int2");
+		assert_serialization_error(upsert(&[Missing{id: 1, name: "Ann".to_string()}], &opts, true));
+	}
+
+	#[test]
+	fn update_rejects_malformed_entities() {
+		assert_type_mismatch(update::<i32, _>(&wrong_type()), "age", "int2");
+		assert_type_mismatch(update::<i32, _>(&WrongKeyType{id: "1".to_string(), name: "Ann".to_string(), age: 1}), "id", "int4");
+		assert_serialization_error(update::<i32, _>(&Missing{id: 1, name: "Ann".to_string()}));
+		assert_serialization_error(update::<i32, _>(&Unknown{id: 1, name: "Ann".to_string(), age: 1, nick: "A".to_string()}));
+	}
+
+	#[test]
+	fn delete_full_match_rejects_malformed_entities() {
+		assert_type_mismatch(delete_full_match(&wrong_type()), "age", "int2");
+		assert_serialization_error(delete_full_match(&Missing{id: 1, name: "Ann".to_string()}));
+		assert_serialization_error(delete_full_match(&Duplicate{id: 1, name: "Ann".to_string(), age: 1}));
+	}
+
+	#[test]
+	fn delete_full_match_needs_a_condition() {
+		match delete_full_match(&OnlySerial{id: 1}) {
+			Err(DbError::UnsupportedOperation(_)) => {}
+			Err(e) => panic!("unexpected error {}", e),
+			Ok(q) => panic!("would run {}", q.text),
+		}
+	}
+
+	#[test]
+	fn quotes_reserved_and_mixed_case_names() {
+		let mut scheme = people();
+		scheme.name = "Order".to_string();
+		scheme.fields[1].name = "user".to_string();
+		scheme.fields[2].constraints.push(Constraint::References("Group".to_string(), "Id".to_string(), None, None));
+		let opts = CreateTableOptions{temp: false, if_not_exists: false};
+		assert_eq!(create_table(&scheme, &opts), "CREATE TABLE \"Order\"(\"id\" serial NOT NULL PRIMARY KEY ,\"user\" text NOT NULL ,\
+			\"age\" smallint NULL REFERENCES \"Group\"(\"Id\") ON DELETE NO ACTION ON UPDATE NO ACTION )");
+		assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+	}
+
+	#[test]
+	fn create_table_emits_table_checks() {
+		let mut scheme = people();
+		scheme.constraints.push(Constraint::Check("age < 200".to_string()));
+		let opts = CreateTableOptions{temp: false, if_not_exists: true};
+		assert_eq!(create_table(&scheme, &opts), "CREATE TABLE IF NOT EXISTS \"people\"(\"id\" serial NOT NULL PRIMARY KEY ,\"name\" text NOT NULL ,\
+			\"age\" smallint NULL ,CHECK (age < 200))");
+	}
+
+	#[test]
+	fn accepts_ordinary_names_and_expressions() {
+		let mut scheme = people();
+		scheme.name = "Person Log".to_string();
+		scheme.constraints.push(Constraint::Check("age > 0 AND (name <> '' OR name = 'it''s; -- fine')".to_string()));
+		scheme.fields[1].constraints.push(Constraint::Default("'a'".to_string()));
+		scheme.fields[2].constraints.push(Constraint::Check("\"age\" < 200".to_string()));
+		assert!(validate_scheme(&scheme).is_ok());
+	}
+
+	#[test]
+	fn rejects_suspicious_names_and_expressions() {
+		let suspicious = |change: &dyn Fn(&mut Scheme)| {
+			let mut scheme = people();
+			change(&mut scheme);
+			match validate_scheme(&scheme) {
+				Err(DbError::SqlInjectionAttempt(_)) => {}
+				Err(e) => panic!("unexpected error {}", e),
+				Ok(_) => panic!("suspicious scheme accepted"),
+			}
+		};
+		suspicious(&|s| s.name = "people\"; DROP TABLE users; --".to_string());
+		suspicious(&|s| s.name = "".to_string());
+		suspicious(&|s| s.name = "x".repeat(64));
+		suspicious(&|s| s.fields[1].name = "name/*".to_string());
+		suspicious(&|s| s.fields[2].constraints.push(Constraint::References("t; DROP TABLE x".to_string(), "id".to_string(), None, None)));
+		suspicious(&|s| s.fields[2].constraints.push(Constraint::References("t".to_string(), "id\0".to_string(), None, None)));
+		suspicious(&|s| s.constraints.push(Constraint::Check("age > 0); DROP TABLE people; --".to_string())));
+		suspicious(&|s| s.constraints.push(Constraint::Check("age > 0), name text CHECK (true".to_string())));
+		suspicious(&|s| s.constraints.push(Constraint::Check("name = 'unclosed".to_string())));
+		suspicious(&|s| s.constraints.push(Constraint::Check("age > 0 -- comment".to_string())));
+		suspicious(&|s| s.constraints.push(Constraint::Check("name = $$x$$".to_string())));
+		suspicious(&|s| s.fields[1].constraints.push(Constraint::Default("'a'; DELETE FROM people".to_string())));
+	}
+
+	#[test]
+	fn writes_refuse_suspicious_schemes() {
+		struct Evil {
+			id: i32,
+		}
+
+		impl Entity for Evil {
+			fn scheme() -> &'static Scheme {
+				static SCHEME: OnceLock<Scheme> = OnceLock::new();
+				SCHEME.get_or_init(|| {
+					let mut scheme = people();
+					scheme.name = "people\"; DROP TABLE people; --".to_string();
+					scheme.fields.truncate(1);
+					scheme
+				})
+			}
+
+			fn __values(&self) -> Vec<(&'static str, &(dyn ToSql + Sync))> {
+				vec!(("id", &self.id))
+			}
+		}
+
+		assert!(matches!(insert(&[Evil{id: 1}], false), Err(DbError::SqlInjectionAttempt(_))));
+		assert!(matches!(select_all::<Evil>(), Err(DbError::SqlInjectionAttempt(_))));
+		assert!(matches!(count::<Evil>(), Err(DbError::SqlInjectionAttempt(_))));
+		assert!(!VALID_SCHEMES.contains(Evil::scheme()));
+	}
+
+	#[test]
+	fn copy_in_rejects_malformed_entities() {
+		let copy = copy_in::<WrongType>().unwrap();
+		assert_type_mismatch(copy.values(&wrong_type()), "age", "int2");
+		let copy = copy_in::<Missing>().unwrap();
+		assert_serialization_error(copy.values(&Missing{id: 1, name: "Ann".to_string()}));
+	}
+}
@@ -0,0 +1,166 @@
+//! Transactions and savepoints over a `PostgresClient`.
+use std::ops::{Deref, DerefMut};
+
+use crate::database::PostgresClient;
+use crate::error::DbError;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IsolationLevel {
+	ReadUncommitted,
+	ReadCommitted,
+	RepeatableRead,
+	Serializable
+}
+
+impl std::fmt::Display for IsolationLevel {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			IsolationLevel::ReadUncommitted => write!(f, "READ UNCOMMITTED"),
+			IsolationLevel::ReadCommitted => write!(f, "READ COMMITTED"),
+			IsolationLevel::RepeatableRead => write!(f, "REPEATABLE READ"),
+			IsolationLevel::Serializable => write!(f, "SERIALIZABLE")
+		}
+	}
+}
+
+#[derive(Clone, Default)]
+pub struct TransactionOptions {
+	pub isolation_level: Option<IsolationLevel>,
+	pub read_only: bool,
+	pub deferrable: bool
+}
+
+impl TransactionOptions {
+	fn begin_statement(&self) -> String {
+		let mut query = String::from("BEGIN");
+		if let Some(level) = &self.isolation_level {
+			query += format!(" ISOLATION LEVEL {}", level).as_str();
+		}
+		if self.read_only {
+			query += " READ ONLY";
+		}
+		if self.deferrable {
+			query += " DEFERRABLE";
+		}
+		query
+	}
+}
+
+/// An open transaction or savepoint. Derefs to `PostgresClient`, so every
+/// CRUD method runs inside it. Rolled back on drop unless committed.
+pub struct Transaction<'a> {
+	client: &'a mut PostgresClient,
+	savepoint: Option<String>,
+	done: bool,
+}
+
+impl<'a> Transaction<'a> {
+	/// Opens a nested transaction with `SAVEPOINT name`. Committing it
+	/// releases the savepoint, dropping or rolling it back undoes only the
+	/// work done since it was created.
+	pub fn savepoint(&mut self, name: &str) -> Result<Transaction<'_>, DbError> {
+		if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+			return Err(DbError::SqlInjectionAttempt(name.to_string()));
+		}
+		self.client.open_savepoint(name)
+	}
+
+	pub fn commit(mut self) -> Result<(), DbError> {
+		let query = match &self.savepoint {
+			Some(name) => format!("RELEASE SAVEPOINT {}", name),
+			None => "COMMIT".to_string(),
+		};
+		self.finish(query.as_str())
+	}
+
+	pub fn rollback(mut self) -> Result<(), DbError> {
+		self.rollback_inner()
+	}
+
+	fn rollback_inner(&mut self) -> Result<(), DbError> {
+		let query = match &self.savepoint {
+			Some(name) => format!("ROLLBACK TO SAVEPOINT {0}; RELEASE SAVEPOINT {0}", name),
+			None => "ROLLBACK".to_string(),
+		};
+		self.finish(query.as_str())
+	}
+
+	fn finish(&mut self, query: &str) -> Result<(), DbError> {
+		self.done = true;
+		self.client.transaction_depth -= 1;
+		self.client.batch_execute(query)
+	}
+}
+
+impl<'a> Drop for Transaction<'a> {
+	fn drop(&mut self) {
+		if !self.done {
+			let _ = self.rollback_inner();
+		}
+	}
+}
+
+impl<'a> Deref for Transaction<'a> {
+	type Target = PostgresClient;
+
+	fn deref(&self) -> &PostgresClient {
+		self.client
+	}
+}
+
+impl<'a> DerefMut for Transaction<'a> {
+	fn deref_mut(&mut self) -> &mut PostgresClient {
+		self.client
+	}
+}
+
+impl PostgresClient {
+	pub fn begin(&mut self) -> Result<Transaction<'_>, DbError> {
+		self.begin_with(&TransactionOptions::default())
+	}
+
+	/// Starts a transaction. If one is already in progress on this client,
+	/// a savepoint is created instead, so helpers that open their own
+	/// transaction can be called from inside another one.
+	pub fn begin_with(&mut self, opts: &TransactionOptions) -> Result<Transaction<'_>, DbError> {
+		if self.transaction_depth > 0 {
+			if opts.isolation_level.is_some() || opts.read_only || opts.deferrable {
+				return Err(DbError::UnsupportedOperation("transaction options in nested transaction".to_string()));
+			}
+			let name = format!("rs_pg_savepoint_{}", self.transaction_depth);
+			return self.open_savepoint(name.as_str());
+		}
+		self.batch_execute(opts.begin_statement().as_str())?;
+		self.transaction_depth += 1;
+		Ok(Transaction{
+			client: self,
+			savepoint: None,
+			done: false,
+		})
+	}
+
+	fn open_savepoint(&mut self, name: &str) -> Result<Transaction<'_>, DbError> {
+		self.batch_execute(format!("SAVEPOINT {}", name).as_str())?;
+		self.transaction_depth += 1;
+		Ok(Transaction{
+			client: self,
+			savepoint: Some(name.to_string()),
+			done: false,
+		})
+	}
+
+	/// Runs `f` inside a transaction, committing if it returns `Ok` and
+	/// rolling back otherwise.
+	pub fn transaction<F, R>(&mut self, f: F) -> Result<R, DbError>
+	where F: FnOnce(&mut Transaction<'_>) -> Result<R, DbError> {
+		self.transaction_with(&TransactionOptions::default(), f)
+	}
+
+	pub fn transaction_with<F, R>(&mut self, opts: &TransactionOptions, f: F) -> Result<R, DbError>
+	where F: FnOnce(&mut Transaction<'_>) -> Result<R, DbError> {
+		let mut tx = self.begin_with(opts)?;
+		let res = f(&mut tx)?;
+		tx.commit()?;
+		Ok(res)
+	}
+}
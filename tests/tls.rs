@@ -0,0 +1,40 @@
+//! Connects to a server with a self-signed certificate. Set `RS_PG_TLS_URL`
+//! to its URL, e.g. `postgresql://postgres@localhost/postgres?sslmode=verify-full&sslrootcert=server.crt`,
+//! and run `cargo test --features native-tls -- --ignored`.
+#![cfg(feature = "native-tls")]
+
+use rs_pg::{AsyncPostgresClient, PostgresClient};
+
+fn tls_url() -> Option<String> {
+	std::env::var("RS_PG_TLS_URL").ok()
+}
+
+const SSL_IN_USE: &str = "SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()";
+
+#[test]
+#[ignore]
+fn connects_with_tls() {
+	let url = match tls_url() {
+		Some(url) => url,
+		None => return,
+	};
+	let mut client = PostgresClient::connect_with_str(&url).unwrap();
+	let ssl: bool = client.query(SSL_IN_USE, &[]).unwrap()[0].get(0);
+	assert!(ssl);
+}
+
+#[test]
+#[ignore]
+fn connects_with_tls_async() {
+	let url = match tls_url() {
+		Some(url) => url,
+		None => return,
+	};
+	let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+	rt.block_on(async {
+		let client = AsyncPostgresClient::connect_with_str(&url).await.unwrap();
+		let ssl: bool = client.query(SSL_IN_USE, &[]).await.unwrap()[0].get(0);
+		assert!(ssl);
+		client.close().await.unwrap();
+	});
+}